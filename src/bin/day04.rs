@@ -1,50 +1,167 @@
+use itertools::Itertools;
+
 fn main() {
     const PUZZLE_DATA: &str = include_str!("day04/puzzle.txt");
     println!("Part1: {}", part1(PUZZLE_DATA));
     println!("Part2: {}", part2(PUZZLE_DATA));
+
+    if std::env::args().any(|arg| arg == "--report") {
+        report(PUZZLE_DATA);
+    }
 }
 
 fn part1(data: &str) -> usize {
-    data.lines()
-        .map(|s| {
-            let (left, right) = s.split_once(',').unwrap();
-
-            let (a, b) = left.split_once('-').unwrap();
-            let (x, y) = right.split_once('-').unwrap();
-
-            [
-                a.parse::<u32>().unwrap(),
-                b.parse::<u32>().unwrap(),
-                x.parse::<u32>().unwrap(),
-                y.parse::<u32>().unwrap(),
-            ]
-        })
-        .filter(|&[a, b, c, d]: &[u32; 4]| ((a <= c) && (b >= d)) || ((a >= c) && (b <= d)))
+    parse(data)
+        .iter()
+        .filter(|assignments| !containments(assignments).is_empty())
         .count()
 }
 
 fn part2(data: &str) -> usize {
-    data.lines()
-        .map(|s| {
-            let (left, right) = s.split_once(',').unwrap();
-
-            let (a, b) = left.split_once('-').unwrap();
-            let (x, y) = right.split_once('-').unwrap();
-
-            [
-                a.parse::<u32>().unwrap(),
-                b.parse::<u32>().unwrap(),
-                x.parse::<u32>().unwrap(),
-                y.parse::<u32>().unwrap(),
-            ]
+    parse(data)
+        .iter()
+        .filter(|assignments| {
+            assignments
+                .iter()
+                .tuple_combinations()
+                .any(|(x, y)| x.overlaps(y))
         })
-        .filter(|&[a, b, c, d]: &[u32; 4]| (b >= c && b <= d) || (d >= a && d <= b))
         .count()
 }
 
+/// Print every pairwise containment, the sections shared by all elves on a
+/// line, and the section covered by the most elves
+fn report(data: &str) {
+    let assignments = parse(data);
+
+    for (line, elves) in assignments.iter().enumerate() {
+        for (i, j) in containments(elves) {
+            println!(
+                "Line {}: elf {} ({}) contains elf {} ({})",
+                line + 1,
+                i + 1,
+                elves[i],
+                j + 1,
+                elves[j]
+            );
+        }
+        if let (Some(common), 3..) = (common_sections(elves), elves.len()) {
+            println!("Line {}: all elves cover {common}", line + 1);
+        }
+    }
+
+    if let Some((section, count)) = most_covered(assignments.iter().flatten()) {
+        println!("Section {section} is covered by {count} elves");
+    }
+}
+
+/// Inclusive range of section IDs, written as `start-end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SectionRange {
+    start: u32,
+    end: u32,
+}
+
+impl SectionRange {
+    fn contains(&self, other: &Self) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    fn intersection(&self, other: &Self) -> Option<Self> {
+        self.overlaps(other).then(|| Self {
+            start: self.start.max(other.start),
+            end: self.end.min(other.end),
+        })
+    }
+}
+
+impl std::str::FromStr for SectionRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Expected start-end, got {s:?}"))?;
+        let parse = |x: &str| {
+            x.parse::<u32>()
+                .map_err(|err| format!("Invalid section {x:?}: {err}"))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+
+        if start > end {
+            return Err(format!("Range {s:?} ends before it starts"));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl std::fmt::Display for SectionRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// Section assignments of each line, which may list any number of elves
+fn parse(data: &str) -> Vec<Vec<SectionRange>> {
+    data.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            line.split(',')
+                .map(|range| {
+                    range
+                        .parse()
+                        .unwrap_or_else(|err| panic!("Line {}: {err}", i + 1))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Every pair of indices `(i, j)` where elf `i`'s range contains elf `j`'s.
+/// Identical ranges contain each other.
+fn containments(assignments: &[SectionRange]) -> Vec<(usize, usize)> {
+    (0..assignments.len())
+        .permutations(2)
+        .map(|pair| (pair[0], pair[1]))
+        .filter(|&(i, j)| assignments[i].contains(&assignments[j]))
+        .collect()
+}
+
+/// Sections covered by every range
+fn common_sections(assignments: &[SectionRange]) -> Option<SectionRange> {
+    let (first, rest) = assignments.split_first()?;
+    rest.iter()
+        .try_fold(*first, |common, range| common.intersection(range))
+}
+
+/// The lowest section covered by the most ranges, with how many cover it
+fn most_covered<'a>(ranges: impl IntoIterator<Item = &'a SectionRange>) -> Option<(u32, usize)> {
+    // Coverage goes up at every start and down right after every end
+    let changes = ranges
+        .into_iter()
+        .flat_map(|range| [(range.start as u64, 1), (range.end as u64 + 1, -1)])
+        .sorted();
+
+    let mut coverage = 0_i64;
+    let mut most: Option<(u32, usize)> = None;
+    for (section, change) in changes {
+        coverage += change;
+        if most.is_none_or(|(_, count)| coverage as usize > count) {
+            most = Some((section as u32, coverage as usize));
+        }
+    }
+
+    most
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
     const DATA: &str = include_str!("day04/sample.txt");
 
     #[test]
@@ -56,4 +173,58 @@ mod tests {
     fn test_part2() {
         assert_eq!(part2(DATA), 4);
     }
+
+    #[rstest]
+    #[case("2-8", "3-7", true, true, Some("3-7"))]
+    #[case("3-7", "2-8", false, true, Some("3-7"))]
+    #[case("5-7", "7-9", false, true, Some("7-7"))]
+    #[case("2-4", "6-8", false, false, None)]
+    #[case("6-6", "4-6", false, true, Some("6-6"))]
+    fn section_range_works(
+        #[case] x: SectionRange,
+        #[case] y: SectionRange,
+        #[case] contains: bool,
+        #[case] overlaps: bool,
+        #[case] intersection: Option<&str>,
+    ) {
+        assert_eq!(x.contains(&y), contains);
+        assert_eq!(x.overlaps(&y), overlaps);
+        assert_eq!(y.overlaps(&x), overlaps);
+        assert_eq!(
+            x.intersection(&y),
+            intersection.map(|range| range.parse().unwrap())
+        );
+    }
+
+    #[rstest]
+    #[case("2")]
+    #[case("2-x")]
+    #[case("8-2")]
+    fn section_range_rejects(#[case] input: &str) {
+        assert!(input.parse::<SectionRange>().is_err());
+    }
+
+    #[test]
+    fn test_n_way_assignments() {
+        let assignments = parse("1-9,2-3,2-3,8-10\n1-2,3-4,5-6");
+        assert_eq!(
+            containments(&assignments[0]),
+            [(0, 1), (0, 2), (1, 2), (2, 1)]
+        );
+        assert!(containments(&assignments[1]).is_empty());
+        assert_eq!(common_sections(&assignments[0]), None);
+        assert_eq!(
+            common_sections(&parse("1-9,2-5,4-8")[0]),
+            Some(SectionRange { start: 4, end: 5 })
+        );
+        assert_eq!(part1("1-9,2-3,2-3,8-10\n1-2,3-4,5-6"), 1);
+        assert_eq!(part2("1-2,3-4,4-6\n1-2,3-4,5-6"), 1);
+    }
+
+    #[test]
+    fn test_most_covered() {
+        let assignments = parse(DATA);
+        assert_eq!(most_covered(assignments.iter().flatten()), Some((6, 8)));
+        assert_eq!(most_covered(&[]), None);
+    }
 }
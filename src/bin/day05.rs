@@ -1,44 +1,245 @@
-#![feature(map_many_mut, iter_collect_into)]
-use advent_of_code_2022::io::parse_day5::{parse, Command};
-use std::collections::HashMap;
+#![feature(iter_collect_into)]
+use advent_of_code_2022::io::parse_day5::{self, Command};
+use itertools::Itertools;
+use std::io::{self, BufRead};
 
 fn main() {
     const PUZZLE_DATA: &str = include_str!("day05/puzzle.txt");
     println!("Part1: {}", part1(PUZZLE_DATA));
     println!("Part2: {}", part2(PUZZLE_DATA));
+
+    if std::env::args().any(|arg| arg == "--step") {
+        step_interactively(PUZZLE_DATA);
+    }
+    if let Some(capacity) = std::env::args()
+        .skip_while(|arg| arg != "--capacity")
+        .nth(1)
+    {
+        let capacity = capacity.parse().expect("Usage: --capacity K");
+        let (top_crates, trips) = capacity_limited(PUZZLE_DATA, capacity);
+        println!("Capacity {capacity}: {top_crates} in {trips} trips");
+    }
+}
+
+/// Top crates and number of trips for a crane lifting at most `capacity`
+/// crates at once
+fn capacity_limited(data: &str, capacity: usize) -> (String, usize) {
+    let (stacks, commands) = parse(data);
+    let mut stacks = Stacks(stacks);
+    let mut crane = CapacityLimited::new(capacity);
+    reorder_stacks(&mut stacks, commands, &mut crane);
+    (stacks.top_crates(), crane.trips)
 }
 
 fn part1(data: &str) -> String {
-    let (mut stacks, commands) = parse(data);
-    reorder_stacks(&mut stacks, commands, true);
-    get_top_crates(&stacks)
+    let (stacks, commands) = parse(data);
+    let mut stacks = Stacks(stacks);
+    reorder_stacks(&mut stacks, commands, &mut OneAtATime);
+    stacks.top_crates()
 }
 
 fn part2(data: &str) -> String {
-    let (mut stacks, commands) = parse(data);
-    reorder_stacks(&mut stacks, commands, false);
-    get_top_crates(&stacks)
+    let (stacks, commands) = parse(data);
+    let mut stacks = Stacks(stacks);
+    reorder_stacks(&mut stacks, commands, &mut MultiCrate);
+    stacks.top_crates()
 }
 
-fn reorder_stacks(stacks: &mut HashMap<u32, Vec<char>>, commands: Vec<Command>, reverse: bool) {
+/// Walk through the rearrangement from stdin: an empty line or `n` steps
+/// forwards, `b` steps backwards and `q` quits
+fn step_interactively(data: &str) {
+    let (stacks, commands) = parse(data);
+    let mut stepper = Stepper::new(Stacks(stacks), commands, OneAtATime);
+    println!("{}", stepper.stacks());
+
+    for line in io::stdin().lock().lines() {
+        let step = match line.unwrap().trim() {
+            "" | "n" => stepper.forward().map(|command| format!("{command}")),
+            "b" => stepper.back().map(|command| format!("undo {command}")),
+            "q" => break,
+            input => {
+                println!("Unknown input {input:?}, expected n, b or q");
+                continue;
+            }
+        };
+
+        match step {
+            Some(step) => println!(
+                "Step {}/{}: {step}\n\n{}",
+                stepper.position(),
+                stepper.commands.len(),
+                stepper.stacks()
+            ),
+            None => println!("Nothing to step to"),
+        }
+    }
+}
+
+fn parse(data: &str) -> (parse_day5::Stacks, Vec<Command>) {
+    parse_day5::parse(data).unwrap_or_else(|err| panic!("{err}"))
+}
+
+fn reorder_stacks(stacks: &mut Stacks, commands: Vec<Command>, crane: &mut impl Crane) {
     for command in commands {
-        let [src, dst] = stacks
-            .get_many_mut([&command.src_stack, &command.dst_stack])
-            .unwrap();
+        crane.execute(stacks, &command);
+    }
+}
+
+trait Crane {
+    /// Move crates from one stack to another as instructed by `command`
+    fn execute(&mut self, stacks: &mut Stacks, command: &Command);
+}
+
+/// CrateMover 9000, moving crates one at a time
+#[derive(Debug, Clone)]
+struct OneAtATime;
+
+impl Crane for OneAtATime {
+    fn execute(&mut self, stacks: &mut Stacks, command: &Command) {
+        let [src, dst] = stacks.src_and_dst(command);
         let final_length = src.len() - command.num_crates_to_move;
+        src.drain(final_length..).rev().collect_into(dst);
+    }
+}
+
+/// CrateMover 9001, moving all crates of a command at once
+#[derive(Debug, Clone)]
+struct MultiCrate;
+
+impl Crane for MultiCrate {
+    fn execute(&mut self, stacks: &mut Stacks, command: &Command) {
+        let [src, dst] = stacks.src_and_dst(command);
+        let final_length = src.len() - command.num_crates_to_move;
+        src.drain(final_length..).collect_into(dst);
+    }
+}
 
-        if reverse {
-            src.drain(final_length..).rev().collect_into(dst);
-        } else {
+/// Moves up to `capacity` crates at once, counting every trip it makes
+#[derive(Debug, Clone)]
+struct CapacityLimited {
+    capacity: usize,
+    trips: usize,
+}
+
+impl CapacityLimited {
+    fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "A crane must lift at least one crate");
+        Self { capacity, trips: 0 }
+    }
+}
+
+impl Crane for CapacityLimited {
+    fn execute(&mut self, stacks: &mut Stacks, command: &Command) {
+        let [src, dst] = stacks.src_and_dst(command);
+        let mut remaining = command.num_crates_to_move;
+
+        while remaining > 0 {
+            let lifted = remaining.min(self.capacity);
+            let final_length = src.len() - lifted;
             src.drain(final_length..).collect_into(dst);
+            remaining -= lifted;
+            self.trips += 1;
         }
     }
 }
 
-fn get_top_crates(stacks: &HashMap<u32, Vec<char>>) -> String {
-    (1..=stacks.len())
-        .map(|idx| *stacks.get(&(idx as u32)).unwrap().last().unwrap())
-        .collect()
+/// Stacks of crates by their ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stacks(parse_day5::Stacks);
+
+impl Stacks {
+    fn src_and_dst(&mut self, command: &Command) -> [&mut Vec<String>; 2] {
+        self.0
+            .get_disjoint_mut([&command.src_stack, &command.dst_stack])
+            .map(Option::unwrap)
+    }
+
+    fn top_crates(&self) -> String {
+        self.0
+            .keys()
+            .sorted()
+            .filter_map(|id| self.0[id].last())
+            .join("")
+    }
+}
+
+/// Drawing in the same format as the puzzle input
+impl std::fmt::Display for Stacks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ids = self.0.keys().copied().sorted().collect::<Vec<_>>();
+        let height = self.0.values().map(Vec::len).max().unwrap_or(0);
+        // Wide enough for the longest label or ID, with both centred so that
+        // every crate sits right above its ID
+        let widths = ids
+            .iter()
+            .map(|id| {
+                let label_width = self.0[id].iter().map(|label| label.len() + 2).max();
+                label_width.unwrap_or(0).max(id.to_string().len()).max(3)
+            })
+            .collect::<Vec<_>>();
+
+        for level in (0..height).rev() {
+            let line = ids
+                .iter()
+                .zip(&widths)
+                .map(|(id, &width)| match self.0[id].get(level) {
+                    Some(label) => format!("{:^width$}", format!("[{label}]")),
+                    None => " ".repeat(width),
+                })
+                .join(" ");
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        let ids = ids
+            .iter()
+            .zip(&widths)
+            .map(|(id, &width)| format!("{id:^width$}"))
+            .join(" ");
+        write!(f, "{}", ids.trim_end())
+    }
+}
+
+/// Applies commands one at a time, keeping every earlier state of the stacks
+/// and the crane so that it can also step backwards
+struct Stepper<C: Crane + Clone> {
+    commands: Vec<Command>,
+    history: Vec<(Stacks, C)>,
+}
+
+impl<C: Crane + Clone> Stepper<C> {
+    fn new(stacks: Stacks, commands: Vec<Command>, crane: C) -> Self {
+        Self {
+            commands,
+            history: vec![(stacks, crane)],
+        }
+    }
+
+    fn stacks(&self) -> &Stacks {
+        &self.history.last().unwrap().0
+    }
+
+    /// Number of commands applied so far
+    fn position(&self) -> usize {
+        self.history.len() - 1
+    }
+
+    /// Apply the next command, returning it
+    fn forward(&mut self) -> Option<Command> {
+        let command = *self.commands.get(self.position())?;
+        let (mut stacks, mut crane) = self.history.last().unwrap().clone();
+        crane.execute(&mut stacks, &command);
+        self.history.push((stacks, crane));
+        Some(command)
+    }
+
+    /// Undo the last command, returning it
+    fn back(&mut self) -> Option<Command> {
+        if self.position() == 0 {
+            return None;
+        }
+        self.history.pop();
+        Some(self.commands[self.position()])
+    }
 }
 
 #[cfg(test)]
@@ -55,4 +256,81 @@ mod tests {
     fn test_part2() {
         assert_eq!(part2(DATA), "MCD");
     }
+
+    #[test]
+    fn test_capacity_limited() {
+        // A single crate at a time, like the CrateMover 9000
+        assert_eq!(capacity_limited(DATA, 1), ("CMZ".to_owned(), 1 + 3 + 2 + 1));
+        // Enough capacity for every command, like the CrateMover 9001
+        assert_eq!(capacity_limited(DATA, 3), ("MCD".to_owned(), 4));
+        // The three crates [Z] [N] [D] go over as [N] [D] then [Z]
+        assert_eq!(capacity_limited(DATA, 2), ("MCZ".to_owned(), 1 + 2 + 1 + 1));
+    }
+
+    #[test]
+    fn test_display_roundtrips() {
+        let (stacks, commands) = parse(DATA);
+        let stacks = Stacks(stacks);
+        let drawing = stacks.to_string();
+        assert_eq!(drawing, "    [D]\n[N] [C]\n[Z] [M] [P]\n 1   2   3");
+
+        let commands = commands.iter().map(Command::to_string).collect::<Vec<_>>();
+        let (parsed, _) = parse(&format!("{drawing}\n\n{}", commands.join("\n")));
+        assert_eq!(Stacks(parsed), stacks);
+
+        let stacks = Stacks(parse_day5::Stacks::from([
+            (1, vec!["A".to_owned()]),
+            (2, vec![]),
+            (3, vec![]),
+        ]));
+        let drawing = stacks.to_string();
+        assert_eq!(drawing, "[A]\n 1   2   3");
+        let (parsed, _) = parse(&drawing);
+        assert_eq!(Stacks(parsed), stacks);
+
+        let stacks = Stacks(
+            (1..=12)
+                .map(|id| (id, vec!["X".repeat(id as usize % 5 + 1)]))
+                .collect(),
+        );
+        let drawing = stacks.to_string();
+        assert!(drawing.ends_with("11   12"), "{drawing}");
+        let (parsed, _) = parse(&drawing);
+        assert_eq!(Stacks(parsed), stacks);
+    }
+
+    #[test]
+    fn test_stepper() {
+        let (stacks, commands) = parse(DATA);
+        let mut stepper = Stepper::new(Stacks(stacks.clone()), commands.clone(), OneAtATime);
+
+        assert_eq!(stepper.back(), None);
+        assert_eq!(stepper.forward(), Some(commands[0]));
+        assert_eq!(
+            stepper.stacks().to_string(),
+            "[D]\n[N] [C]\n[Z] [M] [P]\n 1   2   3"
+        );
+        while stepper.forward().is_some() {}
+        assert_eq!(stepper.position(), commands.len());
+        assert_eq!(stepper.stacks().top_crates(), "CMZ");
+
+        while stepper.back().is_some() {}
+        assert_eq!(stepper.stacks(), &Stacks(stacks.clone()));
+    }
+
+    #[test]
+    fn test_stepper_restores_trips() {
+        let (stacks, commands) = parse(DATA);
+        let mut stepper = Stepper::new(Stacks(stacks), commands, CapacityLimited::new(2));
+        let trips = |stepper: &Stepper<CapacityLimited>| stepper.history.last().unwrap().1.trips;
+
+        stepper.forward();
+        stepper.forward();
+        assert_eq!(trips(&stepper), 1 + 2);
+        stepper.back();
+        assert_eq!(trips(&stepper), 1);
+        while stepper.forward().is_some() {}
+        assert_eq!(trips(&stepper), 1 + 2 + 1 + 1);
+        assert_eq!(stepper.stacks().top_crates(), "MCZ");
+    }
 }
@@ -1,36 +1,123 @@
-use std::collections::HashSet;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, Read},
+};
 
 fn main() {
     const PUZZLE_DATA: &str = include_str!("day06/puzzle.txt");
     println!("Part1: {}", part1(PUZZLE_DATA));
     println!("Part2: {}", part2(PUZZLE_DATA));
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() > 2 && args[1] == "--all" {
+        let seq_len = args[2].parse().expect("Usage: --all SEQ_LEN [PATH]");
+        let reader: Box<dyn Read> = match args.get(3) {
+            Some(path) => {
+                Box::new(File::open(path).unwrap_or_else(|_| panic!("Failed to open {path}")))
+            }
+            None => Box::new(PUZZLE_DATA.as_bytes()),
+        };
+        for position in Markers::new(reader, seq_len) {
+            println!("{}", position.unwrap());
+        }
+    }
 }
 
 fn part1(data: &str) -> usize {
-    find_longest_unique_idx(data, 4)
+    find_marker(data.as_bytes(), 4)
+        .unwrap()
+        .expect("No start-of-packet marker")
 }
 fn part2(data: &str) -> usize {
-    find_longest_unique_idx(data, 14)
-}
-
-fn find_longest_unique_idx(data: &str, seq_len: usize) -> usize {
-    data.as_bytes()
-        .windows(seq_len)
-        .enumerate()
-        .find_map(|(i, arr)| {
-            if arr.iter().collect::<HashSet<&u8>>().len() == seq_len {
-                Some(i + seq_len)
-            } else {
-                None
-            }
-        })
+    find_marker(data.as_bytes(), 14)
         .unwrap()
+        .expect("No start-of-message marker")
+}
+
+/// Number of bytes read up to and including the first `seq_len` distinct bytes
+fn find_marker<R: Read>(reader: R, seq_len: usize) -> io::Result<Option<usize>> {
+    Markers::new(reader, seq_len).next().transpose()
+}
+
+/// Every position right after `seq_len` distinct bytes in a stream.
+///
+/// Keeps a sliding window with a count per byte value, along with how many
+/// byte values appear more than once in it. Each byte is then handled in
+/// constant time, no matter how long the window is.
+struct Markers<R: Read> {
+    bytes: io::Bytes<BufReader<R>>,
+    seq_len: usize,
+    window: VecDeque<u8>,
+    counts: [usize; 256],
+    repeated: usize,
+    position: usize,
+}
+
+impl<R: Read> Markers<R> {
+    fn new(reader: R, seq_len: usize) -> Self {
+        Self {
+            bytes: BufReader::new(reader).bytes(),
+            seq_len,
+            window: VecDeque::with_capacity(seq_len + 1),
+            counts: [0; 256],
+            repeated: 0,
+            position: 0,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Markers<R> {
+    type Item = io::Result<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let byte = match self.bytes.next()? {
+                Ok(byte) => byte,
+                Err(err) => return Some(Err(err)),
+            };
+            self.position += 1;
+
+            self.window.push_back(byte);
+            self.counts[byte as usize] += 1;
+            if self.counts[byte as usize] == 2 {
+                self.repeated += 1;
+            }
+
+            if self.window.len() > self.seq_len {
+                let old = self.window.pop_front().unwrap();
+                self.counts[old as usize] -= 1;
+                if self.counts[old as usize] == 1 {
+                    self.repeated -= 1;
+                }
+            }
+
+            if self.window.len() == self.seq_len && self.repeated == 0 {
+                return Some(Ok(self.position));
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
     use rstest::rstest;
+    use std::collections::HashSet;
+
+    fn find_longest_unique_idx(data: &str, seq_len: usize) -> Option<usize> {
+        data.as_bytes()
+            .windows(seq_len)
+            .enumerate()
+            .find_map(|(i, arr)| {
+                if arr.iter().collect::<HashSet<&u8>>().len() == seq_len {
+                    Some(i + seq_len)
+                } else {
+                    None
+                }
+            })
+    }
 
     #[rstest]
     #[case("mjqjpqmgbljsphdztnvjfqwrcgsmlb", 7)]
@@ -51,4 +138,37 @@ mod tests {
     fn test_part2(#[case] input: &str, #[case] expected: usize) {
         assert_eq!(part2(input), expected);
     }
+
+    #[test]
+    fn test_markers() {
+        assert_eq!(find_marker("aaaa".as_bytes(), 2).unwrap(), None);
+        assert_eq!(
+            Markers::new("abcabba".as_bytes(), 3)
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap(),
+            [3, 4, 5]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn markers_match_windows(data in "[a-e]{0,40}", seq_len in 1..6usize) {
+            let expected = data
+                .as_bytes()
+                .windows(seq_len)
+                .enumerate()
+                .filter(|(_, arr)| arr.iter().collect::<HashSet<_>>().len() == seq_len)
+                .map(|(i, _)| i + seq_len)
+                .collect::<Vec<_>>();
+            let markers = Markers::new(data.as_bytes(), seq_len)
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap();
+
+            prop_assert_eq!(&markers, &expected);
+            prop_assert_eq!(
+                find_marker(data.as_bytes(), seq_len).unwrap(),
+                find_longest_unique_idx(&data, seq_len)
+            );
+        }
+    }
 }
@@ -1,12 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use itertools::Itertools;
 use nom::{
     branch::alt,
-    bytes::complete::{is_a, tag},
-    character::complete::{self, alpha1, newline},
+    bytes::complete::{is_not, tag},
+    character::complete::{self, multispace0, newline, space1},
     combinator::map,
-    multi::separated_list1,
-    sequence::{preceded, terminated},
+    multi::{many0, separated_list1},
+    sequence::{preceded, separated_pair, terminated},
     IResult,
 };
 
@@ -14,70 +15,538 @@ fn main() {
     const PUZZLE_DATA: &str = include_str!("day07/puzzle.txt");
     println!("Part1: {}", part1(PUZZLE_DATA));
     println!("Part2: {}", part2(PUZZLE_DATA));
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() > 1 {
+        let operations = parse(PUZZLE_DATA).unwrap_or_else(|err| panic!("{err}"));
+        let filesystem = Filesystem::from_operations(operations);
+        let arg = |usage: &str| args.get(2).unwrap_or_else(|| panic!("Usage: {usage}"));
+        let print_directories = |directories: Vec<(String, u32)>| {
+            directories
+                .iter()
+                .for_each(|(path, size)| println!("{size}\t{path}"))
+        };
+
+        match args[1].as_str() {
+            "tree" => print!("{filesystem}"),
+            "du" => print_directories(filesystem.du()),
+            "lookup" => {
+                let path = arg("lookup PATH");
+                match filesystem.lookup(path) {
+                    Some(Node::Directory(id)) => {
+                        println!("{path}: directory of {}", filesystem.directory_sizes()[id])
+                    }
+                    Some(Node::File(size)) => println!("{path}: file of {size}"),
+                    None => println!("{path}: not found"),
+                }
+            }
+            "largest" => {
+                let n = arg("largest N").parse().expect("N must be a number");
+                print_directories(filesystem.largest(n))
+            }
+            "glob" => print_directories(filesystem.glob(arg("glob PATTERN"))),
+            "free" => {
+                let needed = arg("free BYTES").parse().expect("BYTES must be a number");
+                match filesystem.smallest_deletion_set(needed) {
+                    Some(directories) => print_directories(directories),
+                    None => println!("Cannot free {needed} bytes"),
+                }
+            }
+            "extensions" => filesystem
+                .sizes_by_extension()
+                .iter()
+                .for_each(|(extension, size)| match extension {
+                    &"" => println!("{size}\t(none)"),
+                    extension => println!("{size}\t{extension}"),
+                }),
+            "transcript" => match args.get(2) {
+                Some(path) => {
+                    let json = std::fs::read_to_string(path)
+                        .unwrap_or_else(|_| panic!("Failed to read {path}"));
+                    let json = serde_json::from_str(&json)
+                        .unwrap_or_else(|err| panic!("Invalid JSON in {path}: {err}"));
+                    let filesystem = Filesystem::from_json(&json)
+                        .unwrap_or_else(|| panic!("{path} does not describe a filesystem"));
+                    print!("{}", filesystem.transcript())
+                }
+                None => print!("{}", filesystem.transcript()),
+            },
+            arg => panic!("Unknown subcommand {arg}"),
+        }
+    }
 }
 
 fn part1(data: &str) -> u32 {
-    let (_, operations) = parse(data).unwrap();
+    let operations = parse(data).unwrap_or_else(|err| panic!("{err}"));
+    let filesystem = Filesystem::from_operations(operations);
 
-    let (_, sizes) = operations
+    filesystem
+        .directory_sizes()
         .into_iter()
-        .fold((vec![], HashMap::new()), calculate_sizes);
-
-    sizes
-        .iter()
-        .filter_map(|(_, &v)| if v < 100000 { Some(v) } else { None })
+        .filter(|&size| size < 100000)
         .sum()
 }
 
 fn part2(data: &str) -> u32 {
-    let (_, operations) = parse(data).unwrap();
-    let (_, sizes) = operations
-        .into_iter()
-        .fold((vec![], HashMap::new()), calculate_sizes);
+    let operations = parse(data).unwrap_or_else(|err| panic!("{err}"));
+    let filesystem = Filesystem::from_operations(operations);
 
     const DEVICE_SIZE: u32 = 70_000_000;
     const UPDATE_SIZE: u32 = 30_000_000;
-    let update_space_deficit = UPDATE_SIZE - (DEVICE_SIZE - sizes.get(&vec!["/"]).unwrap());
-
-    sizes
-        .iter()
-        .filter_map(|(_, &v)| {
-            if v >= update_space_deficit {
-                Some(v)
-            } else {
-                None
+    let needed = filesystem.space_needed(DEVICE_SIZE, UPDATE_SIZE);
+
+    filesystem.smallest_directory_to_free(needed).unwrap()
+}
+
+#[derive(Debug, Default)]
+struct Directory<'a> {
+    name: &'a str,
+    parent: Option<usize>,
+    subdirectories: BTreeMap<&'a str, usize>,
+    files: BTreeMap<&'a str, u32>,
+}
+
+/// Result of [`Filesystem::lookup`]
+#[derive(Debug, PartialEq, Eq)]
+enum Node {
+    /// Index of the directory in the filesystem
+    Directory(usize),
+    /// Size of the file
+    File(u32),
+}
+
+/// Directory tree reconstructed from a terminal log.
+///
+/// Directories are stored in an arena, so a parent always comes before its
+/// subdirectories.
+#[derive(Debug)]
+struct Filesystem<'a> {
+    directories: Vec<Directory<'a>>,
+}
+
+impl<'a> Filesystem<'a> {
+    const ROOT: usize = 0;
+
+    fn new() -> Self {
+        Self {
+            directories: vec![Directory {
+                name: "/",
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn from_operations(operations: impl IntoIterator<Item = Operation<'a>>) -> Self {
+        let mut filesystem = Self::new();
+        let mut current = Self::ROOT;
+
+        for op in operations {
+            match op {
+                Operation::ChangeDirectory(ChangeDirectory::Root) => current = Self::ROOT,
+                Operation::ChangeDirectory(ChangeDirectory::Up) => {
+                    current = filesystem.directories[current].parent.unwrap_or(Self::ROOT)
+                }
+                Operation::ChangeDirectory(ChangeDirectory::Down(name)) => {
+                    current = filesystem.add_directory(current, name)
+                }
+                Operation::ListDirectory(entries) => {
+                    for entry in entries {
+                        match entry {
+                            Entry::Directory(name) => {
+                                filesystem.add_directory(current, name);
+                            }
+                            Entry::File(name, size) => filesystem.add_file(current, name, size),
+                        }
+                    }
+                }
             }
-        })
-        .min()
-        .unwrap()
-}
-
-fn calculate_sizes<'a>(
-    (mut context, mut sizes): (Vec<&'a str>, HashMap<Vec<&'a str>, u32>),
-    op: Operation<'a>,
-) -> (Vec<&'a str>, HashMap<Vec<&'a str>, u32>) {
-    match op {
-        Operation::ChangeDirectory(ChangeDirectory::Root) => context.push("/"),
-        Operation::ChangeDirectory(ChangeDirectory::Up) => {
-            context.pop();
-        }
-        Operation::ChangeDirectory(ChangeDirectory::Down(name)) => context.push(name),
-        Operation::ListDirectory(filesystem_objs) => {
-            let current_directory_size: u32 = filesystem_objs.iter().sum();
-
-            // Update sizes for directory hierarchy
-            for i in 0..context.len() {
-                sizes
-                    .entry(context[0..=i].to_vec())
-                    .and_modify(|size| {
-                        *size += current_directory_size;
-                    })
-                    .or_insert(current_directory_size);
+        }
+
+        filesystem
+    }
+
+    /// Get the subdirectory `name` of `parent`, creating it if needed
+    fn add_directory(&mut self, parent: usize, name: &'a str) -> usize {
+        if let Some(&id) = self.directories[parent].subdirectories.get(name) {
+            return id;
+        }
+
+        let id = self.directories.len();
+        self.directories.push(Directory {
+            name,
+            parent: Some(parent),
+            ..Default::default()
+        });
+        self.directories[parent].subdirectories.insert(name, id);
+        id
+    }
+
+    /// Total size of every directory, indexed like `directories`
+    fn directory_sizes(&self) -> Vec<u32> {
+        let mut sizes = self
+            .directories
+            .iter()
+            .map(|dir| dir.files.values().sum())
+            .collect::<Vec<u32>>();
+
+        // Children always come after their parent
+        for (id, dir) in self.directories.iter().enumerate().skip(1).rev() {
+            sizes[dir.parent.unwrap()] += sizes[id];
+        }
+
+        sizes
+    }
+
+    /// Absolute path of a directory, e.g. `/a/e`
+    fn path(&self, id: usize) -> String {
+        let mut names = vec![];
+        let mut current = id;
+        while let Some(parent) = self.directories[current].parent {
+            names.push(self.directories[current].name);
+            current = parent;
+        }
+
+        format!("/{}", names.iter().rev().join("/"))
+    }
+
+    /// `du`-style listing of every directory path with its total size
+    fn du(&self) -> Vec<(String, u32)> {
+        let sizes = self.directory_sizes();
+        (0..self.directories.len())
+            .map(|id| (self.path(id), sizes[id]))
+            .sorted()
+            .collect()
+    }
+
+    /// Find a file or directory by its absolute path
+    fn lookup(&self, path: &str) -> Option<Node> {
+        let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+        let mut current = Self::ROOT;
+
+        while let Some(name) = names.next() {
+            let dir = &self.directories[current];
+            match dir.subdirectories.get(name) {
+                Some(&id) => current = id,
+                None if names.peek().is_none() => {
+                    return dir.files.get(name).copied().map(Node::File)
+                }
+                None => return None,
             }
         }
+
+        Some(Node::Directory(current))
+    }
+
+    /// Build a filesystem from a JSON object, where every value is either a
+    /// file size or another object for a subdirectory, e.g.
+    /// `{"a": {"e": {"i": 584}}, "b.txt": 14848514}`.
+    ///
+    /// Names that could not appear in a transcript, such as `..` or ones
+    /// with whitespace or `/`, are rejected.
+    fn from_json(root: &'a serde_json::Value) -> Option<Self> {
+        let mut filesystem = Self::new();
+        filesystem.add_json(Self::ROOT, root.as_object()?)?;
+        Some(filesystem)
     }
 
-    (context, sizes)
+    fn add_json(
+        &mut self,
+        parent: usize,
+        entries: &'a serde_json::Map<String, serde_json::Value>,
+    ) -> Option<()> {
+        for (name, value) in entries {
+            if name.is_empty() || name == ".." || name.contains(['/', ' ', '\t', '\r', '\n']) {
+                return None;
+            }
+            match value {
+                serde_json::Value::Object(entries) => {
+                    let id = self.add_directory(parent, name);
+                    self.add_json(id, entries)?;
+                }
+                size => self.add_file(parent, name, size.as_u64()?.try_into().ok()?),
+            }
+        }
+        Some(())
+    }
+
+    fn add_file(&mut self, parent: usize, name: &'a str, size: u32) {
+        self.directories[parent].files.insert(name, size);
+    }
+
+    /// Terminal log that `cd`s into and `ls`es every directory, which parses
+    /// back into the same filesystem
+    fn transcript(&self) -> String {
+        let mut transcript = String::from("$ cd /\n");
+        self.write_transcript(Self::ROOT, &mut transcript);
+        transcript
+    }
+
+    fn write_transcript(&self, id: usize, transcript: &mut String) {
+        let dir = &self.directories[id];
+
+        transcript.push_str("$ ls\n");
+        for name in dir.subdirectories.keys() {
+            transcript.push_str(&format!("dir {name}\n"));
+        }
+        for (name, size) in &dir.files {
+            transcript.push_str(&format!("{size} {name}\n"));
+        }
+
+        for (name, &child) in &dir.subdirectories {
+            transcript.push_str(&format!("$ cd {name}\n"));
+            self.write_transcript(child, transcript);
+            transcript.push_str("$ cd ..\n");
+        }
+    }
+
+    fn fmt_directory(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        id: usize,
+        depth: usize,
+        sizes: &[u32],
+    ) -> std::fmt::Result {
+        let indent = "  ".repeat(depth);
+        let dir = &self.directories[id];
+        writeln!(f, "{indent}- {} (dir, size={})", dir.name, sizes[id])?;
+
+        for &child in dir.subdirectories.values() {
+            self.fmt_directory(f, child, depth + 1, sizes)?;
+        }
+        for (name, size) in &dir.files {
+            writeln!(f, "{indent}  - {name} (file, size={size})")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `tree`-style listing with sizes
+impl std::fmt::Display for Filesystem<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_directory(f, Self::ROOT, 0, &self.directory_sizes())
+    }
+}
+
+/// Queries, each listing directories as (path, total size)
+impl Filesystem<'_> {
+    /// The `n` largest directories, largest first
+    fn largest(&self, n: usize) -> Vec<(String, u32)> {
+        self.du()
+            .into_iter()
+            .sorted_by(|(_, x), (_, y)| y.cmp(x))
+            .take(n)
+            .collect()
+    }
+
+    /// Directories whose path matches `pattern`.
+    ///
+    /// `?` matches one character and `*` any number of them within a path
+    /// component, while `**` matches across components.
+    fn glob(&self, pattern: &str) -> Vec<(String, u32)> {
+        self.du()
+            .into_iter()
+            .filter(|(path, _)| glob_match(pattern.as_bytes(), path.as_bytes()))
+            .collect()
+    }
+
+    /// Bytes still missing to fit an update of `update_size` on a device of
+    /// `device_size`
+    fn space_needed(&self, device_size: u32, update_size: u32) -> u32 {
+        let used = self.directory_sizes()[Self::ROOT];
+        update_size.saturating_sub(device_size.saturating_sub(used))
+    }
+
+    /// Size of the smallest single directory that frees at least `needed` bytes
+    fn smallest_directory_to_free(&self, needed: u32) -> Option<u32> {
+        self.directory_sizes()
+            .into_iter()
+            .filter(|&size| size >= needed)
+            .min()
+    }
+
+    /// Directories that together free at least `needed` bytes while deleting as
+    /// little as possible.
+    ///
+    /// Directories are visited in pre-order, where deleting one skips past its
+    /// whole subtree, so a selection never contains nested directories. A
+    /// total is reachable at position `i` if it can be freed by directories
+    /// that all end before `i`. Only totals below `needed` have to be tracked,
+    /// since deleting anything more from a large enough total only frees more.
+    /// They are kept one bit each, and rather than remembering how each total
+    /// was reached, the pass is repeated to find the directories one by one.
+    fn smallest_deletion_set(&self, needed: u32) -> Option<Vec<(String, u32)>> {
+        if needed == 0 {
+            return Some(vec![]);
+        }
+        let sizes = self.directory_sizes();
+        if needed > sizes[Self::ROOT] {
+            return None;
+        }
+        let mut order = vec![];
+        self.preorder(Self::ROOT, &mut order);
+        let n = needed as usize;
+
+        // The smallest total that frees enough along with each directory
+        let mut best: Option<(usize, usize, usize)> = None;
+        reachable_totals(&order, &sizes, n, |position, reachable| {
+            let size = sizes[order[position].0] as usize;
+            if let Some(total) = reachable.first_from(n.saturating_sub(size)) {
+                if best.is_none_or(|(smallest, _, _)| total + size < smallest) {
+                    best = Some((total + size, position, total));
+                }
+            }
+            true
+        });
+
+        // Any directory ending in time that leads to the remaining total
+        let (_, mut last, mut total) = best?;
+        let mut deleted = vec![order[last].0];
+        while total > 0 {
+            reachable_totals(&order, &sizes, n, |position, reachable| {
+                assert!(position < last, "No directory leads to {total}");
+                let (id, end) = order[position];
+                let size = sizes[id] as usize;
+                if end > last || size > total || !reachable.contains(total - size) {
+                    return true;
+                }
+                deleted.push(id);
+                (total, last) = (total - size, position);
+                false
+            });
+        }
+
+        Some(
+            deleted
+                .into_iter()
+                .map(|id| (self.path(id), sizes[id]))
+                .sorted()
+                .collect(),
+        )
+    }
+
+    /// Directories in pre-order, with the position just past each subtree
+    fn preorder(&self, id: usize, order: &mut Vec<(usize, usize)>) {
+        let position = order.len();
+        order.push((id, 0));
+        for &child in self.directories[id].subdirectories.values() {
+            self.preorder(child, order);
+        }
+        order[position].1 = order.len();
+    }
+
+    /// Total size of files per extension, with `""` for files without one
+    fn sizes_by_extension(&self) -> BTreeMap<&str, u32> {
+        let mut sizes = BTreeMap::new();
+        for (name, size) in self.directories.iter().flat_map(|dir| &dir.files) {
+            let extension = name.rsplit_once('.').map_or("", |(_, extension)| extension);
+            *sizes.entry(extension).or_insert(0) += size;
+        }
+        sizes
+    }
+}
+
+/// Set of totals below a fixed bound, stored as one bit per total
+#[derive(Debug, Clone)]
+struct Totals {
+    words: Vec<u64>,
+    bound: usize,
+}
+
+impl Totals {
+    fn new(bound: usize) -> Self {
+        Self {
+            words: vec![0; bound.div_ceil(64)],
+            bound,
+        }
+    }
+
+    fn contains(&self, total: usize) -> bool {
+        self.words[total / 64] & (1 << (total % 64)) != 0
+    }
+
+    fn insert(&mut self, total: usize) {
+        self.words[total / 64] |= 1 << (total % 64);
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    /// Every total increased by `by`, dropping those that reach the bound
+    fn shifted(&self, by: usize) -> Self {
+        let mut shifted = Self::new(self.bound);
+        let (word_shift, bit_shift) = (by / 64, by % 64);
+        for i in word_shift..self.words.len() {
+            let source = i - word_shift;
+            shifted.words[i] = self.words[source] << bit_shift;
+            if bit_shift > 0 && source > 0 {
+                shifted.words[i] |= self.words[source - 1] >> (64 - bit_shift);
+            }
+        }
+        if !self.bound.is_multiple_of(64) {
+            *shifted.words.last_mut().unwrap() &= (1 << (self.bound % 64)) - 1;
+        }
+        shifted
+    }
+
+    /// Smallest total that is at least `start`
+    fn first_from(&self, start: usize) -> Option<usize> {
+        if start >= self.bound {
+            return None;
+        }
+        let first = self.words[start / 64] & (u64::MAX << (start % 64));
+        std::iter::once(first)
+            .chain(self.words[start / 64 + 1..].iter().copied())
+            .enumerate()
+            .find(|&(_, word)| word != 0)
+            .map(|(i, word)| (start / 64 + i) * 64 + word.trailing_zeros() as usize)
+    }
+}
+
+/// Call `visit` with the totals below `bound` reachable at each position of
+/// `order` until it returns `false`
+fn reachable_totals(
+    order: &[(usize, usize)],
+    sizes: &[u32],
+    bound: usize,
+    mut visit: impl FnMut(usize, &Totals) -> bool,
+) {
+    let mut reachable = Totals::new(bound);
+    reachable.insert(0);
+    // Totals that only become reachable once a deleted subtree ends
+    let mut pending: HashMap<usize, Totals> = HashMap::new();
+
+    for (position, &(id, end)) in order.iter().enumerate() {
+        if let Some(totals) = pending.remove(&position) {
+            reachable.union_with(&totals);
+        }
+        if !visit(position, &reachable) {
+            return;
+        }
+        let freed = reachable.shifted(sizes[id] as usize);
+        match pending.get_mut(&end) {
+            Some(totals) => totals.union_with(&freed),
+            None => {
+                pending.insert(end, freed);
+            }
+        }
+    }
+}
+
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    match (pattern, path) {
+        ([], []) => true,
+        ([b'*', b'*', rest @ ..], _) => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        ([b'*', rest @ ..], _) => (0..=path.len())
+            .take_while(|&i| !path[..i].contains(&b'/'))
+            .any(|i| glob_match(rest, &path[i..])),
+        ([b'?', rest @ ..], [c, path @ ..]) if *c != b'/' => glob_match(rest, path),
+        ([p, rest @ ..], [c, path @ ..]) if p == c => glob_match(rest, path),
+        _ => false,
+    }
 }
 
 #[derive(Debug)]
@@ -87,56 +556,430 @@ enum ChangeDirectory<'a> {
     Down(&'a str),
 }
 
+#[derive(Debug)]
+enum Entry<'a> {
+    Directory(&'a str),
+    File(&'a str, u32),
+}
+
 #[derive(Debug)]
 enum Operation<'a> {
     ChangeDirectory(ChangeDirectory<'a>),
-    ListDirectory(Vec<u32>),
+    ListDirectory(Vec<Entry<'a>>),
 }
 
-fn file_size(s: &str) -> IResult<&str, Option<u32>> {
+/// Anything up to the next whitespace
+fn name(s: &str) -> IResult<&str, &str> {
+    is_not(" \t\r\n")(s)
+}
+
+fn file(s: &str) -> IResult<&str, Entry<'_>> {
     map(
-        terminated(complete::u32, is_a(" qwertyuiopasdfghjklzxcvbnm.")),
-        |x| Some(x),
+        separated_pair(complete::u32, space1, name),
+        |(size, name)| Entry::File(name, size),
     )(s)
 }
 
-fn directory_size(s: &str) -> IResult<&str, Option<u32>> {
-    map(preceded(tag("dir "), alpha1), |_| None)(s)
+fn directory(s: &str) -> IResult<&str, Entry<'_>> {
+    map(preceded(tag("dir "), name), Entry::Directory)(s)
 }
 
 fn ls_command(s: &str) -> IResult<&str, Operation> {
     map(
         preceded(
-            tag("$ ls\n"),
-            separated_list1(newline, alt((file_size, directory_size))),
+            tag("$ ls"),
+            many0(preceded(newline, alt((file, directory)))),
         ),
-        |sizes| Operation::ListDirectory(sizes.iter().filter_map(|&x| x).collect()),
+        Operation::ListDirectory,
     )(s)
 }
 
 fn cd_command(s: &str) -> IResult<&str, Operation> {
-    map(
-        preceded(tag("$ cd "), alt((tag("/"), alpha1, tag("..")))),
-        |input| {
-            let input = match input {
-                "/" => ChangeDirectory::Root,
-                ".." => ChangeDirectory::Up,
-                name => ChangeDirectory::Down(name),
-            };
-            Operation::ChangeDirectory(input)
-        },
-    )(s)
+    map(preceded(tag("$ cd "), name), |input| {
+        let input = match input {
+            "/" => ChangeDirectory::Root,
+            ".." => ChangeDirectory::Up,
+            name => ChangeDirectory::Down(name),
+        };
+        Operation::ChangeDirectory(input)
+    })(s)
 }
 
-fn parse(s: &str) -> IResult<&str, Vec<Operation>> {
-    separated_list1(newline, alt((cd_command, ls_command)))(s)
+/// Line of the terminal log that could not be parsed
+#[derive(Debug, PartialEq, Eq)]
+struct ParseError {
+    line: usize,
+    content: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to parse line {}: {:?}", self.line, self.content)
+    }
+}
+
+fn parse(s: &str) -> Result<Vec<Operation>, ParseError> {
+    let operations = terminated(
+        separated_list1(newline, alt((cd_command, ls_command))),
+        multispace0,
+    )(s);
+
+    // Whatever is left over starts somewhere within the first line that could
+    // not be parsed, or right before it
+    let rest = match operations {
+        Ok(("", operations)) => return Ok(operations),
+        Ok((rest, _)) => rest,
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => err.input,
+        Err(nom::Err::Incomplete(_)) => "",
+    };
+    let rest = rest.trim_start_matches('\n');
+    let offset = s.len() - rest.len();
+    let line_start = s[..offset].rfind('\n').map_or(0, |i| i + 1);
+
+    Err(ParseError {
+        line: s[..offset].matches('\n').count() + 1,
+        content: s[line_start..]
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_owned(),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+    use rstest::rstest;
+    use std::collections::HashMap;
     const DATA: &str = include_str!("day07/sample.txt");
 
+    fn calculate_sizes<'a>(
+        (mut context, mut sizes): (Vec<&'a str>, HashMap<Vec<&'a str>, u32>),
+        op: Operation<'a>,
+    ) -> (Vec<&'a str>, HashMap<Vec<&'a str>, u32>) {
+        match op {
+            Operation::ChangeDirectory(ChangeDirectory::Root) => context.push("/"),
+            Operation::ChangeDirectory(ChangeDirectory::Up) => {
+                context.pop();
+            }
+            Operation::ChangeDirectory(ChangeDirectory::Down(name)) => context.push(name),
+            Operation::ListDirectory(filesystem_objs) => {
+                let current_directory_size: u32 = filesystem_objs
+                    .iter()
+                    .filter_map(|entry| match entry {
+                        Entry::File(_, size) => Some(size),
+                        Entry::Directory(_) => None,
+                    })
+                    .sum();
+
+                // Update sizes for directory hierarchy
+                for i in 0..context.len() {
+                    sizes
+                        .entry(context[0..=i].to_vec())
+                        .and_modify(|size| {
+                            *size += current_directory_size;
+                        })
+                        .or_insert(current_directory_size);
+                }
+            }
+        }
+
+        (context, sizes)
+    }
+
+    #[test]
+    fn test_filesystem() {
+        let operations = parse(DATA).unwrap();
+        let filesystem = Filesystem::from_operations(operations);
+
+        assert_eq!(
+            filesystem.du(),
+            [
+                ("/".to_owned(), 48381165),
+                ("/a".to_owned(), 94853),
+                ("/a/e".to_owned(), 584),
+                ("/d".to_owned(), 24933642),
+            ]
+        );
+        assert_eq!(filesystem.lookup("/a/e/i"), Some(Node::File(584)));
+        assert_eq!(filesystem.lookup("/d/"), Some(Node::Directory(2)));
+        assert_eq!(filesystem.lookup("/a/i"), None);
+        assert_eq!(
+            filesystem.to_string(),
+            "\
+- / (dir, size=48381165)
+  - a (dir, size=94853)
+    - e (dir, size=584)
+      - i (file, size=584)
+    - f (file, size=29116)
+    - g (file, size=2557)
+    - h.lst (file, size=62596)
+  - d (dir, size=24933642)
+    - d.ext (file, size=5626152)
+    - d.log (file, size=8033020)
+    - j (file, size=4060174)
+    - k (file, size=7214296)
+  - b.txt (file, size=14848514)
+  - c.dat (file, size=8504156)
+"
+        );
+    }
+
+    /// Compare directory sizes against the ones from folding the log with
+    /// `calculate_sizes`
+    fn assert_matches_calculate_sizes(data: &str) {
+        let filesystem = Filesystem::from_operations(parse(data).unwrap());
+        let (_, sizes) = parse(data)
+            .unwrap()
+            .into_iter()
+            .fold((vec![], HashMap::new()), calculate_sizes);
+
+        let sizes = sizes
+            .into_iter()
+            .map(|(path, size)| (format!("/{}", path[1..].join("/")), size))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            filesystem.du().into_iter().collect::<HashMap<_, _>>(),
+            sizes
+        );
+    }
+
+    #[test]
+    fn test_filesystem_matches_calculate_sizes() {
+        const PUZZLE_DATA: &str = include_str!("day07/puzzle.txt");
+        assert_matches_calculate_sizes(PUZZLE_DATA);
+    }
+
+    #[test]
+    fn test_transcript() {
+        let json = serde_json::json!({
+            "a": {"e": {"i": 584}, "f": 29116, "g": 2557, "h.lst": 62596},
+            "b.txt": 14848514,
+            "c.dat": 8504156,
+            "d": {"j": 4060174, "d.log": 8033020, "d.ext": 5626152, "k": 7214296},
+        });
+        let filesystem = Filesystem::from_json(&json).unwrap();
+        let transcript = filesystem.transcript();
+
+        assert_eq!(part1(&transcript), 95437);
+        assert_eq!(part2(&transcript), 24933642);
+        assert_eq!(
+            Filesystem::from_operations(parse(DATA).unwrap()).transcript(),
+            transcript
+        );
+        assert_matches_calculate_sizes(&transcript);
+
+        assert!(Filesystem::from_json(&serde_json::json!([1, 2])).is_none());
+        assert!(Filesystem::from_json(&serde_json::json!({"a": "b"})).is_none());
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("..")]
+    #[case("/")]
+    #[case("a/b")]
+    #[case("a b")]
+    #[case("a\tb")]
+    #[case("a\n")]
+    fn from_json_rejects_names(#[case] name: &str) {
+        let file = serde_json::json!({ name: 1 });
+        assert!(Filesystem::from_json(&file).is_none());
+        let directory = serde_json::json!({ "a": { name: {} } });
+        assert!(Filesystem::from_json(&directory).is_none());
+    }
+
+    #[test]
+    fn test_large_transcript() {
+        let names = (0..10).map(|i| format!("dir{i}")).collect::<Vec<_>>();
+        let mut filesystem = Filesystem::new();
+        let mut level = vec![Filesystem::ROOT];
+        for _ in 0..4 {
+            level = level
+                .into_iter()
+                .flat_map(|parent| {
+                    filesystem.add_file(parent, "data.bin", 1000);
+                    names
+                        .iter()
+                        .map(|name| filesystem.add_directory(parent, name))
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+        }
+
+        let transcript = filesystem.transcript();
+        let parsed = Filesystem::from_operations(parse(&transcript).unwrap());
+        assert_eq!(parsed.directories.len(), 11111);
+        assert_eq!(parsed.directory_sizes()[Filesystem::ROOT], 1111 * 1000);
+        assert_eq!(parsed.transcript(), transcript);
+    }
+
+    fn arb_filesystem() -> impl Strategy<Value = serde_json::Value> {
+        let name = "[a-zA-Z0-9][a-zA-Z0-9_.-]{0,7}";
+        let file = (0..1_000_000u32).prop_map(serde_json::Value::from);
+        file.prop_recursive(5, 200, 8, move |inner| {
+            prop::collection::btree_map(name, inner, 0..8)
+                .prop_map(|entries| serde_json::Value::Object(entries.into_iter().collect()))
+        })
+        .prop_map(|json| match json {
+            serde_json::Value::Object(_) => json,
+            file => serde_json::json!({ "file": file }),
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn transcript_roundtrips(json in arb_filesystem()) {
+            let filesystem = Filesystem::from_json(&json).unwrap();
+            let transcript = filesystem.transcript();
+            let parsed = Filesystem::from_operations(parse(&transcript).unwrap());
+
+            prop_assert_eq!(parsed.du(), filesystem.du());
+            prop_assert_eq!(parsed.transcript(), transcript.clone());
+            assert_matches_calculate_sizes(&transcript);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let log = "\
+$ cd /
+$ ls
+dir Build-2
+10 README.md
+$ cd Build-2
+$ ls
+20 lib_v1.so
+$ ls
+20 lib_v1.so
+dir empty
+$ cd empty
+$ ls
+$ cd /
+$ ls
+10 README.md
+";
+        let filesystem = Filesystem::from_operations(parse(log).unwrap());
+        assert_eq!(
+            filesystem.du(),
+            [
+                ("/".to_owned(), 30),
+                ("/Build-2".to_owned(), 20),
+                ("/Build-2/empty".to_owned(), 0),
+            ]
+        );
+
+        assert_eq!(
+            parse("$ cd /\n$ ls\n10 a\nbogus entry\n$ cd a").unwrap_err(),
+            ParseError {
+                line: 4,
+                content: "bogus entry".to_owned()
+            }
+        );
+        assert_eq!(
+            parse("$ cd a b\n$ ls").unwrap_err(),
+            ParseError {
+                line: 1,
+                content: "$ cd a b".to_owned()
+            }
+        );
+        assert_eq!(
+            parse("$ cd /\n$ ls\n10 a 20").unwrap_err(),
+            ParseError {
+                line: 3,
+                content: "10 a 20".to_owned()
+            }
+        );
+        assert_eq!(parse("$ rm -rf /").unwrap_err().line, 1);
+    }
+
+    #[test]
+    fn test_queries() {
+        let filesystem = Filesystem::from_operations(parse(DATA).unwrap());
+
+        assert_eq!(
+            filesystem.largest(2),
+            [("/".to_owned(), 48381165), ("/d".to_owned(), 24933642)]
+        );
+        assert_eq!(filesystem.glob("/a*"), [("/a".to_owned(), 94853)]);
+        assert_eq!(filesystem.glob("/**e"), [("/a/e".to_owned(), 584)]);
+        assert_eq!(filesystem.glob("/?").len(), 2);
+        assert_eq!(filesystem.space_needed(70_000_000, 30_000_000), 8381165);
+
+        // A single directory is best...
+        assert_eq!(
+            filesystem.smallest_deletion_set(8381165),
+            Some(vec![("/d".to_owned(), 24933642)])
+        );
+        // ...even when a nested pair of smaller ones comes close, as deleting
+        // /a already includes /a/e
+        assert_eq!(
+            filesystem.smallest_deletion_set(95000),
+            Some(vec![("/d".to_owned(), 24933642)])
+        );
+        assert_eq!(
+            filesystem.smallest_deletion_set(90000),
+            Some(vec![("/a".to_owned(), 94853)])
+        );
+        assert_eq!(filesystem.smallest_deletion_set(50_000_000), None);
+        assert_eq!(filesystem.smallest_deletion_set(0), Some(vec![]));
+
+        assert_eq!(
+            filesystem.sizes_by_extension(),
+            BTreeMap::from([
+                ("", 29116 + 2557 + 584 + 4060174 + 7214296),
+                ("dat", 8504156),
+                ("ext", 5626152),
+                ("log", 8033020),
+                ("lst", 62596),
+                ("txt", 14848514),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_deletion_set_combines_directories() {
+        let log = "\
+$ cd /
+$ ls
+dir a
+dir b
+dir c
+$ cd a
+$ ls
+60 x
+$ cd ..
+$ cd b
+$ ls
+50 y
+$ cd ..
+$ cd c
+$ ls
+200 z";
+        let filesystem = Filesystem::from_operations(parse(log).unwrap());
+        assert_eq!(filesystem.smallest_directory_to_free(100), Some(200));
+        assert_eq!(
+            filesystem.smallest_deletion_set(100),
+            Some(vec![("/a".to_owned(), 60), ("/b".to_owned(), 50)])
+        );
+    }
+
+    #[test]
+    fn test_totals() {
+        let mut totals = Totals::new(200);
+        for total in [0, 3, 63, 64, 130] {
+            totals.insert(total);
+        }
+        let shifted = totals.shifted(70);
+        assert!([70, 73, 133, 134]
+            .iter()
+            .all(|&total| shifted.contains(total)));
+        assert_eq!(shifted.first_from(0), Some(70));
+        assert_eq!(shifted.first_from(74), Some(133));
+        assert_eq!(shifted.first_from(135), None);
+        assert_eq!(shifted.first_from(500), None);
+    }
+
     #[test]
     fn test_part1() {
         assert_eq!(part1(DATA), 95437);
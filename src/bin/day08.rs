@@ -1,10 +1,12 @@
 /// New crates used this time include:
 /// - `ndarray`
-/// - `rayon` (`par_bridge`9)
-/// - `itertools` (`cartesian_product` and `fold_while`)
-/// - `num-traits` (including the concept of generic `one`s)
-/// Created a trait bound-heavy generic function `scenic_score`.
-use itertools::{FoldWhile, Itertools};
+///
+/// Visibility and scenic scores both come from monotonic-stack sweeps in each
+/// of the four directions, in `visibility_and_scenic_scores`. The original
+/// per-tree functions, using `rayon` (`par_bridge`), `itertools`
+/// (`cartesian_product` and `fold_while`) and `num-traits`, now only live in
+/// the tests to check the sweeps against.
+use itertools::Itertools;
 use ndarray::{Array1, Array2};
 use nom::{
     character::complete::{digit1, newline},
@@ -13,9 +15,6 @@ use nom::{
     multi::separated_list1,
     IResult,
 };
-use num_traits::Unsigned;
-use rayon::prelude::*;
-use std::fmt::Debug;
 
 /// Position of a tree
 type Position = (usize, usize);
@@ -24,96 +23,105 @@ fn main() {
     const PUZZLE_DATA: &str = include_str!("day08/puzzle.txt");
     println!("Part1: {}", part1(PUZZLE_DATA));
     println!("Part2: {}", part2(PUZZLE_DATA));
+
+    let (visible, scenic_scores) = visibility_and_scenic_scores(&parse(PUZZLE_DATA));
+    let visible = visible.mapv(usize::from);
+    for (flag, map) in [
+        ("--export-visibility", &visible),
+        ("--export-scenic", &scenic_scores),
+    ] {
+        if let Some(path) = std::env::args().skip_while(|arg| arg != flag).nth(1) {
+            let export = if path.ends_with(".pgm") {
+                to_pgm(map)
+            } else {
+                to_csv(map)
+            };
+            std::fs::write(&path, export)
+                .unwrap_or_else(|_| panic!("Failed to write map to {path}"));
+        }
+    }
 }
 
 fn part1(data: &str) -> usize {
-    let array = parse(data);
-
-    let inner_visible_trees = (1..(array.nrows() - 1))
-        .cartesian_product(1..(array.ncols() - 1))
-        .par_bridge()
-        .filter(|&(row, col)| is_visible((row, col), &array))
-        .count();
-
-    let external_visible_trees = array.len() - (array.nrows() - 2) * (array.ncols() - 2);
-
-    inner_visible_trees + external_visible_trees
+    let (visible, _) = visibility_and_scenic_scores(&parse(data));
+    visible.iter().filter(|&&v| v).count()
 }
 
 fn part2(data: &str) -> usize {
-    let array = parse(data);
-    (1..(array.nrows() - 1))
-        .cartesian_product(1..(array.ncols() - 1))
-        .par_bridge()
-        .map(|pos| scenic_score(pos, &array))
-        .max()
-        .unwrap()
+    let (_, scenic_scores) = visibility_and_scenic_scores(&parse(data));
+    scenic_scores.into_iter().max().unwrap()
 }
 
-/// Check if a tree is visible along its corresponding row or column
-fn is_visible<T: Ord>(position: (usize, usize), arr: &Array2<T>) -> bool {
-    let tree_height = arr.get(position).unwrap();
+/// Compute whether every tree is visible from outside the grid, and its
+/// scenic score, with one sweep per direction.
+///
+/// Each sweep keeps a stack of the trees seen so far whose heights strictly
+/// decrease. Before pushing a tree, every shorter tree is popped off since it
+/// can no longer block the view of any later tree. Whatever is left on top is
+/// the first tree that blocks the view back towards the edge. Every tree is
+/// pushed and popped at most once per sweep, so the whole grid takes
+/// O(rows * cols).
+fn visibility_and_scenic_scores<T: Ord + Copy>(arr: &Array2<T>) -> (Array2<bool>, Array2<usize>) {
+    let (n_rows, n_cols) = arr.dim();
+    let mut visible = Array2::from_elem((n_rows, n_cols), false);
+    let mut scenic_scores = Array2::from_elem((n_rows, n_cols), 1);
 
-    get_trees(position, arr).iter().any(|tree_positions| {
-        tree_positions
-            .iter()
-            .map(|&pos| arr.get(pos).unwrap())
-            .max()
-            .unwrap()
-            < tree_height
-    })
-}
+    let mut sweep = |line: Vec<Position>| {
+        let mut stack: Vec<usize> = vec![];
 
-/// Check if a tree is visible along its corresponding row or column
-fn scenic_score<T: Debug + Unsigned + PartialOrd + Copy>(
-    position: (usize, usize),
-    arr: &Array2<T>,
-) -> usize {
-    let tree_house_height = *arr.get(position).unwrap();
-
-    get_trees(position, arr)
-        .iter()
-        .map(|trees| {
-            // The get count of a vector of trees whose heights
-            // are monotonically ascending
-            trees
-                .iter()
-                .map(|&pos| *arr.get(pos).unwrap())
-                .fold_while(vec![], |mut acc, height| {
-                    acc.push(height);
-                    if height >= tree_house_height {
-                        FoldWhile::Done(acc)
-                    } else {
-                        FoldWhile::Continue(acc)
-                    }
-                })
-                .into_inner()
-                .len()
-        })
-        .product()
-}
+        for (i, &pos) in line.iter().enumerate() {
+            let height = arr[pos];
+            while stack.last().is_some_and(|&j| arr[line[j]] < height) {
+                stack.pop();
+            }
 
-fn get_trees<T>(position: Position, arr: &Array2<T>) -> [Vec<Position>; 4] {
-    let n_rows = arr.nrows();
-    let n_cols = arr.ncols();
+            match stack.last() {
+                // Blocked by a tree at least as tall
+                Some(&j) => scenic_scores[pos] *= i - j,
+                // Taller than everything up to the edge
+                None => {
+                    visible[pos] = true;
+                    scenic_scores[pos] *= i;
+                }
+            }
+            stack.push(i);
+        }
+    };
 
-    let northern_trees = (0..position.0)
-        .rev()
-        .cartesian_product([position.1])
-        .collect();
-    let eastern_trees = [position.0]
-        .into_iter()
-        .cartesian_product((position.1 + 1)..n_cols)
-        .collect();
-    let southern_trees = ((position.0 + 1)..n_rows)
-        .cartesian_product([position.1])
-        .collect();
-    let western_trees = [position.0]
+    for row in 0..n_rows {
+        sweep((0..n_cols).map(|col| (row, col)).collect());
+        sweep((0..n_cols).rev().map(|col| (row, col)).collect());
+    }
+    for col in 0..n_cols {
+        sweep((0..n_rows).map(|row| (row, col)).collect());
+        sweep((0..n_rows).rev().map(|row| (row, col)).collect());
+    }
+
+    (visible, scenic_scores)
+}
+
+/// One line per row of comma-separated values.
+fn to_csv<T: std::fmt::Display>(arr: &Array2<T>) -> String {
+    arr.rows()
         .into_iter()
-        .cartesian_product((0..position.1).rev())
-        .collect();
+        .map(|row| row.iter().join(",") + "\n")
+        .collect()
+}
 
-    [northern_trees, eastern_trees, southern_trees, western_trees]
+/// Plain greyscale PGM, where the largest value is white.
+fn to_pgm(arr: &Array2<usize>) -> String {
+    let max_value = arr.iter().copied().max().unwrap_or(0).max(1);
+    let levels = max_value.min(u16::MAX.into());
+
+    format!(
+        "P2\n{} {}\n{levels}\n{}",
+        arr.ncols(),
+        arr.nrows(),
+        arr.rows()
+            .into_iter()
+            .map(|row| row.iter().map(|x| x * levels / max_value).join(" ") + "\n")
+            .collect::<String>()
+    )
 }
 
 fn row(s: &str) -> IResult<&str, Vec<u8>> {
@@ -135,9 +143,108 @@ fn parse(s: &str) -> Array2<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use itertools::FoldWhile;
+    use num_traits::Unsigned;
+    use rayon::prelude::*;
     use rstest::rstest;
+    use std::fmt::Debug;
     const DATA: &str = include_str!("day08/sample.txt");
 
+    /// Check if a tree is visible along its corresponding row or column
+    fn is_visible<T: Ord>(position: (usize, usize), arr: &Array2<T>) -> bool {
+        let tree_height = arr.get(position).unwrap();
+
+        get_trees(position, arr).iter().any(|tree_positions| {
+            tree_positions
+                .iter()
+                .map(|&pos| arr.get(pos).unwrap())
+                .max()
+                .unwrap()
+                < tree_height
+        })
+    }
+
+    /// Check if a tree is visible along its corresponding row or column
+    fn scenic_score<T: Debug + Unsigned + PartialOrd + Copy>(
+        position: (usize, usize),
+        arr: &Array2<T>,
+    ) -> usize {
+        let tree_house_height = *arr.get(position).unwrap();
+
+        get_trees(position, arr)
+            .iter()
+            .map(|trees| {
+                // The get count of a vector of trees whose heights
+                // are monotonically ascending
+                trees
+                    .iter()
+                    .map(|&pos| *arr.get(pos).unwrap())
+                    .fold_while(vec![], |mut acc, height| {
+                        acc.push(height);
+                        if height >= tree_house_height {
+                            FoldWhile::Done(acc)
+                        } else {
+                            FoldWhile::Continue(acc)
+                        }
+                    })
+                    .into_inner()
+                    .len()
+            })
+            .product()
+    }
+
+    fn get_trees<T>(position: Position, arr: &Array2<T>) -> [Vec<Position>; 4] {
+        let n_rows = arr.nrows();
+        let n_cols = arr.ncols();
+
+        let northern_trees = (0..position.0)
+            .rev()
+            .cartesian_product([position.1])
+            .collect();
+        let eastern_trees = [position.0]
+            .into_iter()
+            .cartesian_product((position.1 + 1)..n_cols)
+            .collect();
+        let southern_trees = ((position.0 + 1)..n_rows)
+            .cartesian_product([position.1])
+            .collect();
+        let western_trees = [position.0]
+            .into_iter()
+            .cartesian_product((0..position.1).rev())
+            .collect();
+
+        [northern_trees, eastern_trees, southern_trees, western_trees]
+    }
+
+    #[test]
+    fn test_sweeps_match_per_tree_functions() {
+        const PUZZLE_DATA: &str = include_str!("day08/puzzle.txt");
+        for data in [DATA, PUZZLE_DATA] {
+            let arr = parse(data);
+            let (visible, scenic_scores) = visibility_and_scenic_scores(&arr);
+
+            (1..(arr.nrows() - 1))
+                .cartesian_product(1..(arr.ncols() - 1))
+                .par_bridge()
+                .for_each(|pos| {
+                    assert_eq!(visible[pos], is_visible(pos, &arr), "{pos:?}");
+                    assert_eq!(scenic_scores[pos], scenic_score(pos, &arr), "{pos:?}");
+                });
+        }
+    }
+
+    #[test]
+    fn test_maps() {
+        let (visible, scenic_scores) = visibility_and_scenic_scores(&parse(DATA));
+        assert_eq!(
+            to_csv(&visible.mapv(usize::from)),
+            "1,1,1,1,1\n1,1,1,0,1\n1,1,0,1,1\n1,0,1,0,1\n1,1,1,1,1\n"
+        );
+        assert_eq!(scenic_scores[(3, 2)], 8);
+        assert_eq!(scenic_scores.row(0).sum(), 0);
+        assert!(to_pgm(&scenic_scores).starts_with("P2\n5 5\n8\n0 0 0 0 0\n0 1 4 1 0\n"));
+    }
+
     #[test]
     fn test_part1() {
         assert_eq!(part1(DATA), 21);
@@ -12,30 +12,53 @@ fn main() {
     const PUZZLE_DATA: &str = include_str!("day09/puzzle.txt");
     println!("Part1: {}", part1(PUZZLE_DATA));
     println!("Part2: {}", part2(PUZZLE_DATA));
-}
 
-fn part1(data: &str) -> usize {
-    let (_, moves) = parse(data).unwrap();
-    let mut rope = Rope::new(2);
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() > 1 {
+        let (_, moves) = parse(PUZZLE_DATA).unwrap();
+        let rope = simulate(10, &moves);
 
-    for (move_action, n_times) in moves {
-        for _ in 0..n_times {
-            rope.move_rope(move_action);
+        if args.iter().any(|arg| arg == "--playback") {
+            rope.frames().for_each(|frame| println!("{frame}\n"));
+        }
+        if args.iter().any(|arg| arg == "--visited") {
+            println!("{}", rope.render_visited(rope.knots.len() - 1));
+        }
+        if let Some(path) = args.iter().skip_while(|arg| *arg != "--csv").nth(1) {
+            std::fs::write(path, rope.trajectories_csv())
+                .unwrap_or_else(|_| panic!("Failed to write trajectories to {path}"));
         }
     }
-    rope.tail_history.len()
+}
+
+fn part1(data: &str) -> usize {
+    let (_, moves) = parse(data).unwrap();
+    simulate_general(2, &moves).history[1].len()
 }
 
 fn part2(data: &str) -> usize {
     let (_, moves) = parse(data).unwrap();
-    let mut rope = Rope::new(10);
+    simulate_general(10, &moves).history[9].len()
+}
+
+fn simulate_general(num_knots: usize, moves: &[(Move, i32)]) -> GeneralRope<2> {
+    let mut rope = GeneralRope::new(num_knots, 1);
 
-    for (move_action, n_times) in moves {
+    for &(move_action, n_times) in moves {
+        rope.move_head(move_action.delta().map(|d| d * n_times));
+    }
+    rope
+}
+
+fn simulate(num_knots: usize, moves: &[(Move, i32)]) -> Rope {
+    let mut rope = Rope::new(num_knots);
+
+    for &(move_action, n_times) in moves {
         for _ in 0..n_times {
             rope.move_rope(move_action);
         }
     }
-    rope.tail_history.len()
+    rope
 }
 
 type Position = (i32, i32);
@@ -58,18 +81,87 @@ impl Move {
             _ => panic!("Invalid direction obtained"),
         }
     }
+
+    fn delta(self) -> [i32; 2] {
+        match self {
+            Self::Up => [0, 1],
+            Self::Down => [0, -1],
+            Self::Left => [-1, 0],
+            Self::Right => [1, 0],
+        }
+    }
+}
+
+/// A rope in `D` dimensions where each knot stays within `slack` steps
+/// (diagonals included) of the knot ahead of it.
+#[derive(Debug)]
+struct GeneralRope<const D: usize> {
+    knots: Vec<[i32; D]>,
+    slack: u32,
+    /// Every position visited by each knot.
+    history: Vec<HashSet<[i32; D]>>,
+}
+
+impl<const D: usize> GeneralRope<D> {
+    fn new(num_knots: usize, slack: u32) -> Self {
+        Self {
+            knots: vec![[0; D]; num_knots],
+            slack,
+            history: vec![HashSet::from([[0; D]]); num_knots],
+        }
+    }
+
+    /// Move the head by an arbitrary `delta`.
+    ///
+    /// The head travels one step at a time, moving along every axis that still
+    /// has distance left, and the body follows after each step.
+    fn move_head(&mut self, delta: [i32; D]) {
+        let mut remaining = delta;
+
+        while remaining.iter().any(|&d| d != 0) {
+            let step = remaining.map(i32::signum);
+            remaining = std::array::from_fn(|axis| remaining[axis] - step[axis]);
+            self.knots[0] = std::array::from_fn(|axis| self.knots[0][axis] + step[axis]);
+
+            self.move_body();
+            self.knots
+                .iter()
+                .zip(&mut self.history)
+                .for_each(|(&knot, history)| {
+                    history.insert(knot);
+                });
+        }
+    }
+
+    /// A knot that is too far from the knot ahead takes one step towards it
+    /// along every axis on which they differ.
+    fn move_body(&mut self) {
+        for i in 1..self.knots.len() {
+            let prev = self.knots[i - 1];
+            let current = self.knots[i];
+            let delta: [i32; D] = std::array::from_fn(|axis| prev[axis] - current[axis]);
+
+            if delta.iter().any(|d| d.unsigned_abs() > self.slack) {
+                self.knots[i] = std::array::from_fn(|axis| current[axis] + delta[axis].signum());
+            }
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 struct Rope {
     knots: Vec<Position>,
     tail_history: HashSet<Position>,
+    /// Positions of every knot, initially and after each step.
+    trajectory: Vec<Vec<Position>>,
 }
 
 impl Rope {
     pub fn new(num_knots: usize) -> Self {
+        let knots = vec![Default::default(); num_knots];
         Self {
-            knots: vec![Default::default(); num_knots],
+            trajectory: vec![knots.clone()],
+            knots,
             tail_history: Default::default(),
         }
     }
@@ -78,6 +170,7 @@ impl Rope {
         self.move_head(move_action);
         self.move_body();
         self.update_tail_history();
+        self.trajectory.push(self.knots.clone());
     }
 
     fn move_head(&mut self, move_action: Move) {
@@ -120,6 +213,90 @@ impl Rope {
     fn update_tail_history(&mut self) {
         self.tail_history.insert(*self.knots.last().unwrap());
     }
+
+    /// Smallest (x_min, x_max, y_min, y_max) that contains every knot at
+    /// every step, so that all frames share the same size.
+    fn bounds(&self) -> (i32, i32, i32, i32) {
+        let positions = || self.trajectory.iter().flatten();
+        (
+            positions().map(|pos| pos.0).min().unwrap(),
+            positions().map(|pos| pos.0).max().unwrap(),
+            positions().map(|pos| pos.1).min().unwrap(),
+            positions().map(|pos| pos.1).max().unwrap(),
+        )
+    }
+
+    /// Draw a grid in the style of the puzzle text, with the greatest y at
+    /// the top. `symbol_at` returns `None` for empty cells.
+    fn render(&self, symbol_at: impl Fn(Position) -> Option<char>) -> String {
+        let (x_min, x_max, y_min, y_max) = self.bounds();
+
+        (y_min..=y_max)
+            .rev()
+            .map(|y| {
+                (x_min..=x_max)
+                    .map(|x| symbol_at((x, y)).unwrap_or('.'))
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn knot_symbol(&self, i: usize) -> char {
+        match i {
+            0 => 'H',
+            1 if self.knots.len() == 2 => 'T',
+            i => char::from_digit(i as u32, 36).unwrap_or('T'),
+        }
+    }
+
+    fn render_knots(&self, knots: &[Position]) -> String {
+        self.render(|pos| {
+            knots
+                .iter()
+                .position(|&knot| knot == pos)
+                .map(|i| self.knot_symbol(i))
+                .or((pos == (0, 0)).then_some('s'))
+        })
+    }
+
+    /// One frame per step of [`Rope::move_rope`], starting with the initial state.
+    fn frames(&self) -> impl Iterator<Item = String> + '_ {
+        self.trajectory.iter().map(|knots| self.render_knots(knots))
+    }
+
+    /// Map of every cell visited by knot `knot`, marked with `#`.
+    fn render_visited(&self, knot: usize) -> String {
+        let visited = self
+            .trajectory
+            .iter()
+            .map(|knots| knots[knot])
+            .collect::<HashSet<_>>();
+
+        self.render(|pos| match (pos, visited.contains(&pos)) {
+            ((0, 0), _) => Some('s'),
+            (_, true) => Some('#'),
+            (_, false) => None,
+        })
+    }
+
+    /// One `step,knot,x,y` row per knot and step.
+    fn trajectories_csv(&self) -> String {
+        std::iter::once("step,knot,x,y".to_owned())
+            .chain(
+                self.trajectory
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(step, knots)| {
+                        knots
+                            .iter()
+                            .enumerate()
+                            .map(move |(knot, (x, y))| format!("{step},{knot},{x},{y}"))
+                    }),
+            )
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
 
 fn parse(s: &str) -> IResult<&str, Vec<(Move, i32)>> {
@@ -159,4 +336,71 @@ L 25
 U 20";
         assert_eq!(part2(input), 36)
     }
+
+    #[test]
+    fn test_general_rope_matches_rope() {
+        let complex = "R 5\nU 8\nL 8\nD 3\nR 17\nD 10\nL 25\nU 20";
+        for data in [DATA, complex] {
+            let (_, moves) = parse(data).unwrap();
+            for num_knots in [2, 10] {
+                let rope = simulate(num_knots, &moves);
+                let general = simulate_general(num_knots, &moves);
+
+                assert_eq!(
+                    general.knots,
+                    rope.knots.iter().map(|&(x, y)| [x, y]).collect::<Vec<_>>()
+                );
+                assert_eq!(
+                    general.history[num_knots - 1],
+                    rope.tail_history.iter().map(|&(x, y)| [x, y]).collect()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_general_rope() {
+        // Diagonal moves drag the body along the diagonal.
+        let mut rope = GeneralRope::<2>::new(3, 1);
+        rope.move_head([3, 3]);
+        assert_eq!(rope.knots, vec![[3, 3], [2, 2], [1, 1]]);
+
+        // Knots only start moving once they are further than `slack` away.
+        let mut rope = GeneralRope::<2>::new(2, 2);
+        rope.move_head([2, 1]);
+        assert_eq!(rope.knots, vec![[2, 1], [0, 0]]);
+        rope.move_head([1, 0]);
+        assert_eq!(rope.knots, vec![[3, 1], [1, 1]]);
+        assert_eq!(rope.history[1], HashSet::from([[0, 0], [1, 1]]));
+
+        let mut rope = GeneralRope::<3>::new(3, 1);
+        rope.move_head([0, 0, 3]);
+        rope.move_head([2, -1, 0]);
+        assert_eq!(rope.knots, vec![[2, -1, 3], [1, -1, 3], [1, -1, 2]]);
+        assert_eq!(rope.history[2].len(), 3);
+    }
+
+    #[test]
+    fn test_render() {
+        let (_, moves) = parse(DATA).unwrap();
+        let rope = simulate(2, &moves);
+
+        let frames = rope.frames().collect::<Vec<_>>();
+        assert_eq!(frames.len(), 1 + 24);
+        assert_eq!(frames[0], "......\n......\n......\n......\nH.....");
+        assert_eq!(frames[4], "......\n......\n......\n......\ns..TH.");
+        assert_eq!(frames[5], "......\n......\n......\n....H.\ns..T..");
+        assert_eq!(
+            rope.render_visited(1),
+            "..##..\n...##.\n.####.\n....#.\ns###.."
+        );
+
+        let rope = simulate(10, &moves[..1]);
+        assert_eq!(rope.frames().last().unwrap(), "4321H");
+
+        let csv = rope.trajectories_csv();
+        assert_eq!(csv.lines().next(), Some("step,knot,x,y"));
+        assert_eq!(csv.lines().count(), 1 + 5 * 10);
+        assert!(csv.ends_with("4,0,4,0\n4,1,3,0\n4,2,2,0\n4,3,1,0\n4,4,0,0\n4,5,0,0\n4,6,0,0\n4,7,0,0\n4,8,0,0\n4,9,0,0"));
+    }
 }
@@ -1,118 +1,614 @@
+use std::collections::BTreeMap;
+
 use itertools::Itertools;
 use nom::{
-    branch::alt,
-    bytes::complete::tag,
-    character::complete::{self, newline},
-    combinator::map,
+    character::complete::{self, alpha1, newline, space1},
+    combinator::{map_opt, opt},
     multi::separated_list1,
-    sequence::preceded,
+    sequence::{pair, preceded},
     IResult,
 };
 
 fn main() {
     const PUZZLE_DATA: &str = include_str!("day10/puzzle.txt");
+    if std::env::args().any(|arg| arg == "--trace") {
+        trace(PUZZLE_DATA);
+    }
+    if let Some(text) = std::env::args()
+        .skip_while(|arg| arg != "--assemble")
+        .nth(1)
+    {
+        let geometry = ScreenGeometry::default();
+        let Some(picture) = render_text(&text, geometry) else {
+            eprintln!("Cannot draw {text:?}: it must fit on screen and use known glyphs");
+            std::process::exit(1);
+        };
+        match generate_program(&picture, geometry.sprite_width) {
+            Some(program) => println!("{program}"),
+            None => {
+                eprintln!(
+                    "Cannot draw {text:?}: the register starts at 1, so the first two pixels \
+                     must be lit and the first letter must be one of B, E, F, P, R or Z"
+                );
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(path) = std::env::args().skip_while(|arg| arg != "--export").nth(1) {
+        let crt = run_crt(PUZZLE_DATA, ScreenGeometry::default()).unwrap();
+        let image = if path.ends_with(".ppm") {
+            crt.to_ppm()
+        } else {
+            crt.to_pbm()
+        };
+        std::fs::write(&path, image).unwrap_or_else(|_| panic!("Failed to write image to {path}"));
+    }
     println!("Part1: {}", part1(PUZZLE_DATA));
-    println!("Part2:\n{}", part2(PUZZLE_DATA));
+    println!("Part2: {}", part2(PUZZLE_DATA));
 }
 
 fn part1(data: &str) -> i32 {
-    let (_, instructions) = parse(data).unwrap();
-    let mut clock_circuit = ClockCircuit::new();
+    let (_, instructions) = parse(data, &INSTRUCTION_SET).unwrap();
+    let mut signal_strength = SignalStrength::default();
 
-    for instruction in instructions {
-        clock_circuit.process(instruction);
-    }
+    ClockCircuit::new()
+        .run(&instructions, &mut [&mut signal_strength])
+        .unwrap();
 
-    clock_circuit.signal_strength
+    signal_strength.total
 }
 
 fn part2(data: &str) -> String {
-    let (_, instructions) = parse(data).unwrap();
-    let mut clock_circuit = ClockCircuit::new();
+    let crt = run_crt(data, ScreenGeometry::default()).unwrap_or_else(|err| panic!("{err}"));
+    ocr(&crt.display_chars).unwrap_or_else(|err| panic!("{err}"))
+}
 
-    for instruction in instructions {
-        clock_circuit.process(instruction);
-    }
+fn run_crt(data: &str, geometry: ScreenGeometry) -> Result<Crt, CycleError> {
+    let (_, instructions) = parse(data, &INSTRUCTION_SET).unwrap();
+    let mut crt = Crt::new(geometry);
 
-    clock_circuit
-        .display_chars
-        .iter()
-        .map(|x| x.iter().collect::<String>())
-        .join("\n")
+    ClockCircuit::new().run(&instructions, &mut [&mut crt])?;
+
+    Ok(crt)
+}
+
+fn trace(data: &str) {
+    let (_, instructions) = parse(data, &INSTRUCTION_SET).unwrap();
+    ClockCircuit::new()
+        .run(&instructions, &mut [&mut Tracer])
+        .unwrap();
+}
+
+/// What an instruction does to the registers once its last cycle completes.
+#[derive(Debug, Clone, Copy)]
+enum Effect {
+    Nothing,
+    /// Add the argument to the register.
+    Add(char),
 }
 
 #[derive(Debug)]
-enum CpuInstruction {
-    AddX(i32),
-    NoOp,
+struct InstructionSpec {
+    name: &'static str,
+    cycles: u32,
+    effect: Effect,
+}
+
+const INSTRUCTION_SET: [InstructionSpec; 2] = [
+    InstructionSpec {
+        name: "noop",
+        cycles: 1,
+        effect: Effect::Nothing,
+    },
+    InstructionSpec {
+        name: "addx",
+        cycles: 2,
+        effect: Effect::Add('x'),
+    },
+];
+
+#[derive(Debug)]
+struct CpuInstruction<'a> {
+    spec: &'a InstructionSpec,
+    arg: Option<i32>,
+}
+
+impl std::fmt::Display for CpuInstruction<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.arg {
+            Some(arg) => write!(f, "{} {arg}", self.spec.name),
+            None => write!(f, "{}", self.spec.name),
+        }
+    }
+}
+
+type Registers = BTreeMap<char, i32>;
+
+#[derive(Debug, PartialEq, Eq)]
+enum CycleError {
+    /// The program ran for more cycles than the screen has pixels.
+    ScreenOverflow { cycle: u32, n_pixels: usize },
+}
+
+impl std::fmt::Display for CycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ScreenOverflow { cycle, n_pixels } => write!(
+                f,
+                "Cycle {cycle} has no pixel to draw, the screen only has {n_pixels} pixels"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ConfigError {
+    /// The screen has no rows or no columns.
+    EmptyScreen { width: usize, height: usize },
+    /// The sprite covers no pixels.
+    EmptySprite,
+    /// Signal strength is sampled every 0 cycles.
+    ZeroInterval,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyScreen { width, height } => {
+                write!(f, "A {width}x{height} screen has no pixels")
+            }
+            Self::EmptySprite => write!(f, "The sprite must be at least one pixel wide"),
+            Self::ZeroInterval => write!(f, "The sampling interval must be at least one cycle"),
+        }
+    }
+}
+
+/// Hook that is called once for every cycle, with the register values as
+/// they are *during* that cycle. Returning an error stops the circuit.
+trait CycleObserver {
+    fn on_cycle(
+        &mut self,
+        cycle: u32,
+        instruction: &CpuInstruction,
+        registers: &Registers,
+    ) -> Result<(), CycleError>;
 }
 
 #[derive(Debug)]
 struct ClockCircuit {
-    register_val: i32,
+    registers: Registers,
     cycle_count: u32,
-    signal_strength: i32,
-    display_chars: [[char; 40]; 6],
 }
 
 impl ClockCircuit {
     fn new() -> Self {
         Self {
-            register_val: 1,
+            registers: Registers::from([('x', 1)]),
             cycle_count: 0,
-            signal_strength: 0,
-            display_chars: [['.'; 40]; 6],
         }
     }
 
-    fn process(&mut self, instruction: CpuInstruction) {
-        match instruction {
-            CpuInstruction::NoOp => self.tick(1),
-            CpuInstruction::AddX(val) => {
-                self.tick(2);
-                self.register_val += val;
+    fn run(
+        &mut self,
+        instructions: &[CpuInstruction],
+        observers: &mut [&mut dyn CycleObserver],
+    ) -> Result<(), CycleError> {
+        instructions
+            .iter()
+            .try_for_each(|instruction| self.process(instruction, observers))
+    }
+
+    fn process(
+        &mut self,
+        instruction: &CpuInstruction,
+        observers: &mut [&mut dyn CycleObserver],
+    ) -> Result<(), CycleError> {
+        for _ in 0..instruction.spec.cycles {
+            self.cycle_count += 1;
+            observers.iter_mut().try_for_each(|observer| {
+                observer.on_cycle(self.cycle_count, instruction, &self.registers)
+            })?;
+        }
+
+        let arg = instruction.arg.unwrap_or_default();
+        match instruction.spec.effect {
+            Effect::Nothing => {}
+            Effect::Add(register) => *self.registers.entry(register).or_default() += arg,
+        }
+
+        Ok(())
+    }
+}
+
+/// Sums `cycle * x` over the cycles `first`, `first + interval`, ...
+#[derive(Debug)]
+struct SignalStrength {
+    first: u32,
+    interval: u32,
+    total: i32,
+}
+
+impl Default for SignalStrength {
+    fn default() -> Self {
+        Self::new(20, 40).unwrap()
+    }
+}
+
+impl SignalStrength {
+    fn new(first: u32, interval: u32) -> Result<Self, ConfigError> {
+        if interval == 0 {
+            return Err(ConfigError::ZeroInterval);
+        }
+        Ok(Self {
+            first,
+            interval,
+            total: 0,
+        })
+    }
+}
+
+impl CycleObserver for SignalStrength {
+    fn on_cycle(
+        &mut self,
+        cycle: u32,
+        _: &CpuInstruction,
+        registers: &Registers,
+    ) -> Result<(), CycleError> {
+        if cycle >= self.first && cycle % self.interval == self.first % self.interval {
+            self.total += (cycle as i32) * registers[&'x'];
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ScreenGeometry {
+    width: usize,
+    height: usize,
+    /// Number of pixels covered by the sprite, centred on the `x` register.
+    sprite_width: usize,
+}
+
+impl Default for ScreenGeometry {
+    fn default() -> Self {
+        Self::new(40, 6, 3).unwrap()
+    }
+}
+
+impl ScreenGeometry {
+    fn new(width: usize, height: usize, sprite_width: usize) -> Result<Self, ConfigError> {
+        if width == 0 || height == 0 {
+            return Err(ConfigError::EmptyScreen { width, height });
+        }
+        if sprite_width == 0 {
+            return Err(ConfigError::EmptySprite);
+        }
+        Ok(Self {
+            width,
+            height,
+            sprite_width,
+        })
+    }
+
+    /// Whether the sprite centred on `x` covers column `col`.
+    fn sprite_covers(&self, x: i32, col: usize) -> bool {
+        let offset = col as i32 - x;
+        -((self.sprite_width as i32 - 1) / 2) <= offset && offset <= self.sprite_width as i32 / 2
+    }
+}
+
+#[derive(Debug)]
+struct Crt {
+    geometry: ScreenGeometry,
+    display_chars: Vec<Vec<char>>,
+}
+
+impl Crt {
+    fn new(geometry: ScreenGeometry) -> Self {
+        Self {
+            geometry,
+            display_chars: vec![vec!['.'; geometry.width]; geometry.height],
+        }
+    }
+
+    /// Plain (ASCII) PBM image, with lit pixels in black.
+    fn to_pbm(&self) -> String {
+        format!(
+            "P1\n{} {}\n{}\n",
+            self.geometry.width,
+            self.geometry.height,
+            self.display_chars
+                .iter()
+                .map(|row| row
+                    .iter()
+                    .map(|&c| if c == '#' { '1' } else { '0' })
+                    .join(" "))
+                .join("\n")
+        )
+    }
+
+    /// Plain (ASCII) PPM image, with lit pixels in green on black.
+    fn to_ppm(&self) -> String {
+        format!(
+            "P3\n{} {}\n255\n{}\n",
+            self.geometry.width,
+            self.geometry.height,
+            self.display_chars
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|&c| if c == '#' { "0 255 0" } else { "0 0 0" })
+                        .join("  ")
+                })
+                .join("\n")
+        )
+    }
+}
+
+impl CycleObserver for Crt {
+    fn on_cycle(
+        &mut self,
+        cycle: u32,
+        _: &CpuInstruction,
+        registers: &Registers,
+    ) -> Result<(), CycleError> {
+        let pixel = cycle as usize - 1;
+        let (row, col) = (pixel / self.geometry.width, pixel % self.geometry.width);
+        if row >= self.geometry.height {
+            return Err(CycleError::ScreenOverflow {
+                cycle,
+                n_pixels: self.geometry.width * self.geometry.height,
+            });
+        }
+
+        if self.geometry.sprite_covers(registers[&'x'], col) {
+            self.display_chars[row][col] = '#';
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Crt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.display_chars
+                .iter()
+                .map(|x| x.iter().collect::<String>())
+                .join("\n")
+        )
+    }
+}
+
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+/// Horizontal distance between the start of two consecutive glyphs.
+const GLYPH_STRIDE: usize = GLYPH_WIDTH + 1;
+
+/// The capital letters of the 4x6 font that Advent of Code draws on CRTs.
+const GLYPHS: [(char, [&str; GLYPH_HEIGHT]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Y', ["#...", "#...", ".#.#", "..#.", "..#.", "..#."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+enum OcrError {
+    /// The screen has fewer rows than a glyph.
+    TooShort { height: usize },
+    UnknownGlyph {
+        /// Position of the glyph on the screen, counting from the left.
+        index: usize,
+        glyph: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for OcrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooShort { height } => write!(
+                f,
+                "A screen of {height} rows cannot fit glyphs of {GLYPH_HEIGHT} rows"
+            ),
+            Self::UnknownGlyph { index, glyph } => {
+                write!(
+                    f,
+                    "Unknown glyph at position {index}:\n{}",
+                    glyph.join("\n")
+                )
             }
         }
     }
+}
+
+/// Read the letters drawn on the screen.
+fn ocr(display_chars: &[Vec<char>]) -> Result<String, OcrError> {
+    if display_chars.len() < GLYPH_HEIGHT {
+        return Err(OcrError::TooShort {
+            height: display_chars.len(),
+        });
+    }
 
-    fn tick(&mut self, n: u32) {
-        for _ in 0..n {
-            self.cycle_count += 1;
-            self.update_display();
-            if self.cycle_count % 40 == 20 {
-                // dbg!(&self, (self.cycle_count as i32) * self.register);
-                self.signal_strength += (self.cycle_count as i32) * self.register_val;
+    (0..display_chars[0].len() / GLYPH_STRIDE)
+        .map(|index| {
+            let glyph = display_chars[..GLYPH_HEIGHT]
+                .iter()
+                .map(|row| {
+                    row[index * GLYPH_STRIDE..index * GLYPH_STRIDE + GLYPH_WIDTH]
+                        .iter()
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>();
+
+            GLYPHS
+                .iter()
+                .find(|(_, pattern)| glyph == *pattern)
+                .map(|&(letter, _)| letter)
+                .ok_or(OcrError::UnknownGlyph { index, glyph })
+        })
+        .collect()
+}
+
+/// Draw `text` with the CRT font, in the top left of an otherwise dark screen.
+fn render_text(text: &str, geometry: ScreenGeometry) -> Option<Vec<Vec<char>>> {
+    if geometry.height < GLYPH_HEIGHT {
+        return None;
+    }
+    let mut display_chars = vec![vec!['.'; geometry.width]; geometry.height];
+
+    for (i, letter) in text.chars().enumerate() {
+        let (_, pattern) = GLYPHS.iter().find(|(glyph, _)| *glyph == letter)?;
+        let offset = i * GLYPH_STRIDE;
+        if offset + GLYPH_WIDTH > display_chars[0].len() {
+            return None;
+        }
+
+        for (row, line) in display_chars.iter_mut().zip(pattern) {
+            row[offset..offset + GLYPH_WIDTH]
+                .iter_mut()
+                .zip(line.chars())
+                .for_each(|(pixel, c)| *pixel = c);
+        }
+    }
+
+    Some(display_chars)
+}
+
+/// Generate an `addx`/`noop` program that draws exactly `picture` on the CRT.
+///
+/// The register can only change once an `addx` has run for two cycles, so not
+/// every picture is drawable; `None` is returned for those, as well as for
+/// empty pictures or sprites. In particular the register starts at 1, so the
+/// first two pixels must be lit.
+fn generate_program(picture: &[Vec<char>], sprite_width: usize) -> Option<String> {
+    let geometry = ScreenGeometry::new(picture.first()?.len(), picture.len(), sprite_width).ok()?;
+    let pixels = picture
+        .iter()
+        .flatten()
+        .map(|&c| c == '#')
+        .collect::<Vec<_>>();
+    let n_cycles = pixels.len();
+
+    // Register values worth considering. Anything further out lights no
+    // pixel at all.
+    let x_min = -(sprite_width as i32);
+    let x_max = (geometry.width + sprite_width) as i32;
+
+    let draws_pixel =
+        |cycle: usize, x: i32| geometry.sprite_covers(x, cycle % geometry.width) == pixels[cycle];
+    let idx = |x: i32| (x - x_min) as usize;
+
+    // `reached[n][idx(x)]` holds the register value before, and the `addx`
+    // argument (if any) of, the first instruction found that leaves the
+    // register at `x` after `n` cycles.
+    let mut reached = vec![vec![None; idx(x_max) + 1]; n_cycles + 1];
+    reached[0][idx(1)] = Some((1, None));
+
+    for cycle in 0..n_cycles {
+        for x in x_min..=x_max {
+            if reached[cycle][idx(x)].is_none() || !draws_pixel(cycle, x) {
+                continue;
+            }
+
+            reached[cycle + 1][idx(x)].get_or_insert((x, None));
+
+            if cycle + 1 < n_cycles && draws_pixel(cycle + 1, x) {
+                for target in x_min..=x_max {
+                    reached[cycle + 2][idx(target)].get_or_insert((x, Some(target - x)));
+                }
             }
         }
     }
 
-    fn update_display(&mut self) {
-        const SCREEN_WIDTH: usize = 40;
-        if ((((self.cycle_count - 1) % SCREEN_WIDTH as u32) as i32) - self.register_val).abs() <= 1
-        {
-            self.display_chars[(self.cycle_count as usize) / SCREEN_WIDTH]
-                [(self.cycle_count as usize - 1) % SCREEN_WIDTH] = '#';
+    let mut x = (x_min..=x_max).find(|&x| reached[n_cycles][idx(x)].is_some())?;
+    let mut cycle = n_cycles;
+    let mut program = vec![];
+
+    while cycle > 0 {
+        let (previous_x, arg) = reached[cycle][idx(x)].unwrap();
+        match arg {
+            None => {
+                program.push("noop".to_owned());
+                cycle -= 1;
+            }
+            Some(arg) => {
+                program.push(format!("addx {arg}"));
+                cycle -= 2;
+            }
         }
+        x = previous_x;
     }
+
+    program.reverse();
+    Some(program.join("\n"))
 }
 
-fn addx(s: &str) -> IResult<&str, CpuInstruction> {
-    map(preceded(tag("addx "), complete::i32), |num| {
-        CpuInstruction::AddX(num)
-    })(s)
+struct Tracer;
+
+impl CycleObserver for Tracer {
+    fn on_cycle(
+        &mut self,
+        cycle: u32,
+        instruction: &CpuInstruction,
+        registers: &Registers,
+    ) -> Result<(), CycleError> {
+        println!(
+            "{cycle:>4} {:<10} {}",
+            instruction.to_string(),
+            registers
+                .iter()
+                .map(|(name, val)| format!("{name}={val}"))
+                .join(" ")
+        );
+        Ok(())
+    }
 }
 
-fn noop(s: &str) -> IResult<&str, CpuInstruction> {
-    map(tag("noop"), |_| CpuInstruction::NoOp)(s)
+fn instruction<'a, 's>(
+    instruction_set: &'a [InstructionSpec],
+) -> impl FnMut(&'s str) -> IResult<&'s str, CpuInstruction<'a>> {
+    map_opt(
+        pair(alpha1, opt(preceded(space1, complete::i32))),
+        |(name, arg)| {
+            let spec = instruction_set.iter().find(|spec| spec.name == name)?;
+            match (spec.effect, arg) {
+                (Effect::Nothing, None) | (Effect::Add(_), Some(_)) => {
+                    Some(CpuInstruction { spec, arg })
+                }
+                _ => None,
+            }
+        },
+    )
 }
 
-fn parse(s: &str) -> IResult<&str, Vec<CpuInstruction>> {
-    separated_list1(newline, alt((addx, noop)))(s)
+fn parse<'a, 's>(
+    s: &'s str,
+    instruction_set: &'a [InstructionSpec],
+) -> IResult<&'s str, Vec<CpuInstruction<'a>>> {
+    separated_list1(newline, instruction(instruction_set))(s)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
     const DATA: &str = include_str!("day10/sample.txt");
 
     #[test]
@@ -122,19 +618,220 @@ mod tests {
 
     #[test]
     fn test_part2() {
+        const PUZZLE_DATA: &str = include_str!("day10/puzzle.txt");
+        assert_eq!(part2(PUZZLE_DATA), "PZULBAUA");
+    }
+
+    #[test]
+    fn test_screen() {
         let expected = "##..##..##..##..##..##..##..##..##..##..
 ###...###...###...###...###...###...###.
 ####....####....####....####....####....
 #####.....#####.....#####.....#####.....
-######......######......######......###.
-#######.......#######.......#######....#"
+######......######......######......####
+#######.......#######.......#######....."
             .to_owned();
 
-        let output = part2(DATA);
+        let output = run_crt(DATA, ScreenGeometry::default())
+            .unwrap()
+            .to_string();
 
         println!("Output:\n{output}");
         println!("Expected:\n{expected}");
 
         assert_eq!(output, expected);
     }
+
+    #[test]
+    fn test_ocr() {
+        let mut display_chars = vec![vec!['.'; 40]; 6];
+        for (i, letter) in "HELLO".chars().enumerate() {
+            let (_, pattern) = GLYPHS.iter().find(|(l, _)| *l == letter).unwrap();
+            for (row, line) in pattern.iter().enumerate() {
+                for (col, c) in line.chars().enumerate() {
+                    display_chars[row][i * GLYPH_STRIDE + col] = c;
+                }
+            }
+        }
+        assert_eq!(
+            ocr(&display_chars),
+            Err(OcrError::UnknownGlyph {
+                index: 5,
+                glyph: vec!["....".to_owned(); GLYPH_HEIGHT],
+            })
+        );
+
+        let crt = run_crt(DATA, ScreenGeometry::default()).unwrap();
+        let Err(OcrError::UnknownGlyph { index, glyph }) = ocr(&crt.display_chars) else {
+            panic!("Expected an unknown glyph");
+        };
+        assert_eq!(index, 0);
+        assert_eq!(glyph[0], "##..");
+
+        assert_eq!(
+            ocr(&display_chars[..5]),
+            Err(OcrError::TooShort { height: 5 })
+        );
+        assert_eq!(ocr(&[]), Err(OcrError::TooShort { height: 0 }));
+    }
+
+    #[rstest]
+    #[case("BACKLOGS", ScreenGeometry::default())]
+    #[case("FUJIPRY", ScreenGeometry::default())]
+    #[case("ZEH", ScreenGeometry::default())]
+    #[case("ZEH", ScreenGeometry::new(16, 8, 3).unwrap())]
+    #[case("PZULBAUA", ScreenGeometry::new(48, 6, 5).unwrap())]
+    fn generate_program_draws_text(#[case] text: &str, #[case] geometry: ScreenGeometry) {
+        let picture = render_text(text, geometry).unwrap();
+        let program = generate_program(&picture, geometry.sprite_width).unwrap();
+        assert_eq!(run_crt(&program, geometry).unwrap().display_chars, picture);
+    }
+
+    #[test]
+    fn test_generate_program() {
+        const PUZZLE_DATA: &str = include_str!("day10/puzzle.txt");
+        let geometry = ScreenGeometry::default();
+        for data in [DATA, PUZZLE_DATA] {
+            let picture = run_crt(data, geometry).unwrap().display_chars;
+            let program = generate_program(&picture, 3).unwrap();
+            assert_eq!(run_crt(&program, geometry).unwrap().display_chars, picture);
+        }
+
+        // The register cannot move away before the second cycle is drawn.
+        let mut picture = vec![vec!['.'; 40]; 6];
+        picture[0][0] = '#';
+        assert_eq!(generate_program(&picture, 3), None);
+        let picture = render_text("ABC", geometry).unwrap();
+        assert_eq!(generate_program(&picture, 3), None);
+
+        // Only letters whose top left two pixels are lit can come first
+        let drawable_first = GLYPHS
+            .iter()
+            .filter(|&&(letter, _)| {
+                let picture = render_text(&letter.to_string(), geometry).unwrap();
+                generate_program(&picture, 3).is_some()
+            })
+            .map(|&(letter, _)| letter)
+            .collect::<String>();
+        assert_eq!(drawable_first, "BEFPRZ");
+        let picture = render_text("HELLO", geometry).unwrap();
+        assert_eq!(generate_program(&picture, 3), None);
+
+        assert_eq!(render_text("ABCDEFGHI", geometry), None);
+        assert_eq!(render_text("abc", geometry), None);
+    }
+
+    #[test]
+    fn test_geometry() {
+        let geometry = ScreenGeometry::new(20, 12, 3).unwrap();
+        let crt = run_crt(DATA, geometry).unwrap();
+        assert_eq!(crt.display_chars.len(), 12);
+        assert_eq!(crt.to_string().lines().next(), Some("##..##..##..##..##.."));
+
+        let crt = run_crt(DATA, ScreenGeometry::new(40, 5, 3).unwrap());
+        assert_eq!(
+            crt.unwrap_err(),
+            CycleError::ScreenOverflow {
+                cycle: 201,
+                n_pixels: 200
+            }
+        );
+
+        let crt = run_crt(
+            "noop\naddx 1\nnoop\nnoop\nnoop",
+            ScreenGeometry::new(3, 2, 1).unwrap(),
+        );
+        assert_eq!(crt.unwrap().to_string(), ".#.\n..#");
+
+        let (_, instructions) = parse(DATA, &INSTRUCTION_SET).unwrap();
+        let mut signal_strength = SignalStrength::new(0, 1).unwrap();
+        ClockCircuit::new()
+            .run(&instructions[..2], &mut [&mut signal_strength])
+            .unwrap();
+        assert_eq!(signal_strength.total, 1 + 2 + (3 + 4) * 16);
+    }
+
+    #[rstest]
+    #[case(0, 6, 3, ConfigError::EmptyScreen { width: 0, height: 6 })]
+    #[case(40, 0, 3, ConfigError::EmptyScreen { width: 40, height: 0 })]
+    #[case(40, 6, 0, ConfigError::EmptySprite)]
+    fn screen_geometry_rejects(
+        #[case] width: usize,
+        #[case] height: usize,
+        #[case] sprite_width: usize,
+        #[case] expected: ConfigError,
+    ) {
+        assert_eq!(
+            ScreenGeometry::new(width, height, sprite_width).unwrap_err(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert_eq!(
+            SignalStrength::new(20, 0).unwrap_err(),
+            ConfigError::ZeroInterval
+        );
+        let picture = render_text("ZEH", ScreenGeometry::default()).unwrap();
+        assert_eq!(generate_program(&picture, 0), None);
+        assert_eq!(generate_program(&[], 3), None);
+    }
+
+    #[test]
+    fn test_export() {
+        let crt = run_crt("noop\nnoop", ScreenGeometry::new(2, 1, 1).unwrap());
+        let crt = crt.unwrap();
+        assert_eq!(crt.to_pbm(), "P1\n2 1\n0 1\n");
+        assert_eq!(crt.to_ppm(), "P3\n2 1\n255\n0 0 0  0 255 0\n");
+    }
+
+    #[derive(Default)]
+    struct History(Vec<(u32, String, Registers)>);
+
+    impl CycleObserver for History {
+        fn on_cycle(
+            &mut self,
+            cycle: u32,
+            instruction: &CpuInstruction,
+            registers: &Registers,
+        ) -> Result<(), CycleError> {
+            self.0
+                .push((cycle, instruction.to_string(), registers.clone()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_extended_instruction_set() {
+        let instruction_set = [
+            InstructionSpec {
+                name: "noop",
+                cycles: 1,
+                effect: Effect::Nothing,
+            },
+            InstructionSpec {
+                name: "addy",
+                cycles: 3,
+                effect: Effect::Add('y'),
+            },
+        ];
+        let (_, instructions) = parse("addy 5\naddy -2\nnoop", &instruction_set).unwrap();
+        let mut history = History::default();
+        let mut circuit = ClockCircuit::new();
+        circuit.run(&instructions, &mut [&mut history]).unwrap();
+
+        assert_eq!(circuit.cycle_count, 7);
+        assert_eq!(circuit.registers, Registers::from([('x', 1), ('y', 3)]));
+        assert_eq!(
+            history.0[3],
+            (
+                4,
+                "addy -2".to_owned(),
+                Registers::from([('x', 1), ('y', 5)])
+            )
+        );
+        assert!(parse("addx 1", &instruction_set).is_err());
+        assert!(parse("addy", &instruction_set).is_err());
+    }
 }
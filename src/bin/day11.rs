@@ -1,19 +1,30 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
+use itertools::Itertools;
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::{self, digit1, multispace0, multispace1, one_of},
-    combinator::map,
-    multi::{many1, separated_list0},
-    sequence::{delimited, separated_pair},
+    character::complete::{self, digit1, multispace0, multispace1, space0},
+    combinator::{map, value},
+    multi::{many0, many1, separated_list0},
+    sequence::{delimited, pair, preceded, separated_pair},
     IResult,
 };
+use num_bigint::BigUint;
+use num_traits::Zero;
+use rayon::prelude::*;
 
 fn main() {
     const PUZZLE_DATA: &str = include_str!("day11/puzzle.txt");
+    if std::env::args().any(|arg| arg == "--trace") {
+        trace_part1(PUZZLE_DATA);
+    }
+    if std::env::args().any(|arg| arg == "--check-exact") {
+        check_exact(PUZZLE_DATA);
+    }
     println!("Part1: {}", part1(PUZZLE_DATA));
     println!("Part2: {}", part2(PUZZLE_DATA));
+    println!("Part2 (by item): {}", part2_by_item(PUZZLE_DATA));
 }
 
 fn part1(data: &str) -> u64 {
@@ -21,27 +32,248 @@ fn part1(data: &str) -> u64 {
     process(&mut monkeys, 20, true)
 }
 
+fn trace_part1(data: &str) {
+    let (_, mut monkeys) = parse(data).unwrap();
+    let (_, trace) = process_traced(&mut monkeys, 20, true);
+
+    for round in 1..=20 {
+        for event in trace.events_in_round(round) {
+            println!(
+                "Monkey {} inspects {} -> {}, {}divisible, thrown to monkey {}",
+                event.monkey,
+                event.old,
+                event.new,
+                if event.divisible { "" } else { "not " },
+                event.target
+            );
+        }
+        println!("{}\n", trace.round(round).unwrap());
+    }
+}
+
+fn check_exact(data: &str) {
+    for (n_rounds, worry_level_decrease) in [(20, true), (10, false)] {
+        let (_, mut monkeys) = parse(data).unwrap();
+        let exact = exact_throws(&monkeys, n_rounds, worry_level_decrease);
+        let (_, trace) = process_traced(&mut monkeys, n_rounds, worry_level_decrease);
+        let mismatch = trace
+            .events
+            .iter()
+            .zip(&exact)
+            .position(|(event, &throw)| (event.monkey, event.target) != throw);
+
+        match mismatch {
+            None => println!("{n_rounds} rounds: modular throws match exact throws"),
+            Some(i) => println!(
+                "{n_rounds} rounds: throw #{i} differs: {:?}",
+                trace.events[i]
+            ),
+        }
+    }
+}
+
 fn part2(data: &str) -> u64 {
     let (_, mut monkeys) = parse(data).unwrap();
     process(&mut monkeys, 10_000, false)
 }
 
 fn process(monkeys: &mut Vec<Monkey>, n_rounds: u64, worry_level_decerase: bool) -> u64 {
+    simulate(monkeys, n_rounds, worry_level_decerase, None)
+}
+
+fn part2_by_item(data: &str) -> u128 {
+    let (_, monkeys) = parse(data).unwrap();
+    process_by_item(&monkeys, 10_000, false)
+}
+
+/// Alternative to [`process`] that follows every item on its own.
+///
+/// An item's path only depends on its own worry level, and worry levels are
+/// reduced modulo the product of the divisors, so each item eventually revisits
+/// a (monkey, worry) state at the start of a round. Inspection counts are then
+/// extrapolated from that cycle, which makes billions of rounds feasible.
+fn process_by_item(monkeys: &[Monkey], n_rounds: u64, worry_level_decerase: bool) -> u128 {
     let divisor_prod: u64 = monkeys
         .iter()
         .map(|monkey| monkey.decision_data.divisor)
         .product();
 
+    let mut num_items_inspected = monkeys
+        .iter()
+        .enumerate()
+        .flat_map(|(src, monkey)| monkey.items.iter().map(move |&item| (src, item)))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|start| {
+            ItemOrbit::new(monkeys, start, worry_level_decerase, divisor_prod)
+                .n_inspected_after(n_rounds)
+        })
+        .reduce(
+            || vec![0; monkeys.len()],
+            |a, b| a.iter().zip(b).map(|(x, y)| x + y).collect(),
+        );
+
+    num_items_inspected.sort_by(|a, b| b.cmp(a));
+    num_items_inspected[0] as u128 * num_items_inspected[1] as u128
+}
+
+/// Per-monkey inspection counts of a single item, up to the point where the
+/// item's state at the start of a round repeats.
+struct ItemOrbit {
+    /// `prefix_counts[r]` holds the counts after `r` rounds.
+    prefix_counts: Vec<Vec<u64>>,
+    cycle_start: usize,
+    cycle_len: usize,
+}
+
+impl ItemOrbit {
+    fn new(
+        monkeys: &[Monkey],
+        (mut src, mut item): (usize, u64),
+        worry_level_decerase: bool,
+        divisor_prod: u64,
+    ) -> Self {
+        let mut seen = HashMap::new();
+        let mut prefix_counts = vec![vec![0; monkeys.len()]];
+
+        let cycle_start = loop {
+            if let Some(&cycle_start) = seen.get(&(src, item)) {
+                break cycle_start;
+            }
+            seen.insert((src, item), prefix_counts.len() - 1);
+            let mut counts = prefix_counts.last().unwrap().clone();
+
+            // The item keeps moving within a round as long as it is thrown to a
+            // monkey whose turn has not come yet.
+            loop {
+                counts[src] += 1;
+                let (new_item, target) =
+                    monkeys[src].inspect(item, worry_level_decerase, divisor_prod);
+                let thrown_forward = target > src;
+                (src, item) = (target, new_item);
+                if !thrown_forward {
+                    break;
+                }
+            }
+
+            prefix_counts.push(counts);
+        };
+
+        Self {
+            cycle_len: prefix_counts.len() - 1 - cycle_start,
+            prefix_counts,
+            cycle_start,
+        }
+    }
+
+    fn n_inspected_after(&self, n_rounds: u64) -> Vec<u64> {
+        if n_rounds < self.prefix_counts.len() as u64 {
+            return self.prefix_counts[n_rounds as usize].clone();
+        }
+
+        let n_cycles = (n_rounds - self.cycle_start as u64) / self.cycle_len as u64;
+        let remainder = ((n_rounds - self.cycle_start as u64) % self.cycle_len as u64) as usize;
+        let start = &self.prefix_counts[self.cycle_start];
+        let end = &self.prefix_counts[self.cycle_start + self.cycle_len];
+        let partial = &self.prefix_counts[self.cycle_start + remainder];
+
+        (0..start.len())
+            .map(|i| partial[i] + n_cycles * (end[i] - start[i]))
+            .collect()
+    }
+}
+
+/// Run the simulation on arbitrary-precision worry levels without any modular
+/// shortcut, returning the (monkey, target) of every throw in order.
+///
+/// Worry levels grow very quickly without the shortcut, so this is only meant
+/// as an oracle over a handful of rounds.
+fn exact_throws(
+    monkeys: &[Monkey],
+    n_rounds: u64,
+    worry_level_decerase: bool,
+) -> Vec<(usize, usize)> {
+    let mut items = monkeys
+        .iter()
+        .map(|monkey| {
+            monkey
+                .items
+                .iter()
+                .map(|&item| BigUint::from(item))
+                .collect::<VecDeque<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut throws = vec![];
+
     for _ in 0..n_rounds {
+        for src in 0..monkeys.len() {
+            while let Some(item) = items[src].pop_front() {
+                let (item, target) = monkeys[src].inspect_exact(&item, worry_level_decerase);
+                throws.push((src, target));
+                items[target].push_back(item);
+            }
+        }
+    }
+
+    throws
+}
+
+/// Like [`process`], but also records every inspection and the items held
+/// after each round.
+fn process_traced(
+    monkeys: &mut [Monkey],
+    n_rounds: u64,
+    worry_level_decerase: bool,
+) -> (u64, Trace) {
+    let mut trace = Trace::default();
+    let monkey_business = simulate(monkeys, n_rounds, worry_level_decerase, Some(&mut trace));
+    (monkey_business, trace)
+}
+
+fn simulate(
+    monkeys: &mut [Monkey],
+    n_rounds: u64,
+    worry_level_decerase: bool,
+    mut trace: Option<&mut Trace>,
+) -> u64 {
+    let divisor_prod: u64 = monkeys
+        .iter()
+        .map(|monkey| monkey.decision_data.divisor)
+        .product();
+
+    for round in 1..=n_rounds {
         for src in 0..monkeys.len() {
             for _ in 0..monkeys[src].items.len() {
                 let src_monkey = &mut monkeys[src];
+                let old = *src_monkey.items.front().unwrap();
                 let (item, target) =
                     src_monkey.inspect_item_and_throw_to(worry_level_decerase, divisor_prod);
+
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.events.push(InspectionEvent {
+                        round,
+                        monkey: src,
+                        old,
+                        new: item,
+                        divisible: item % src_monkey.decision_data.divisor == 0,
+                        target,
+                    });
+                }
+
                 let target_monkey = &mut monkeys[target];
                 target_monkey.receive_item(item);
             }
         }
+
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.snapshots.push(RoundSnapshot {
+                round,
+                items: monkeys
+                    .iter()
+                    .map(|monkey| monkey.items.iter().copied().collect())
+                    .collect(),
+            });
+        }
     }
 
     let mut num_items_inspected: Vec<_> = monkeys.iter().map(|monkey| monkey.n_inspected).collect();
@@ -49,11 +281,123 @@ fn process(monkeys: &mut Vec<Monkey>, n_rounds: u64, worry_level_decerase: bool)
     num_items_inspected[0] * num_items_inspected[1]
 }
 
+/// A single item being inspected by `monkey` and thrown to `target`.
+#[derive(Debug, PartialEq, Eq)]
+struct InspectionEvent {
+    round: u64,
+    monkey: usize,
+    old: u64,
+    new: u64,
+    divisible: bool,
+    target: usize,
+}
+
+/// Worry levels held by each monkey at the end of `round`.
 #[derive(Debug)]
-enum Operation {
-    Add(u64),
-    Mult(u64),
-    Square,
+struct RoundSnapshot {
+    round: u64,
+    items: Vec<Vec<u64>>,
+}
+
+impl std::fmt::Display for RoundSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "After round {}, the monkeys are holding items with these worry levels:",
+            self.round
+        )?;
+        write!(
+            f,
+            "{}",
+            self.items
+                .iter()
+                .enumerate()
+                .map(|(i, items)| format!("Monkey {i}: {}", items.iter().join(", ")))
+                .join("\n")
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Trace {
+    events: Vec<InspectionEvent>,
+    snapshots: Vec<RoundSnapshot>,
+}
+
+impl Trace {
+    fn round(&self, round: u64) -> Option<&RoundSnapshot> {
+        self.snapshots
+            .get(usize::try_from(round).ok()?.checked_sub(1)?)
+    }
+
+    fn events_in_round(&self, round: u64) -> impl Iterator<Item = &InspectionEvent> {
+        self.events.iter().filter(move |event| event.round == round)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Add,
+    Mul,
+}
+
+/// Arithmetic over the old worry level, e.g. `(old + 2) * old`.
+///
+/// Only operators that are compatible with reducing the worry level modulo
+/// the product of the divisors are supported.
+#[derive(Debug, Clone)]
+enum Expr {
+    Old,
+    Literal(u64),
+    BinOp {
+        left: Box<Expr>,
+        operator: Operator,
+        right: Box<Expr>,
+    },
+}
+
+impl Expr {
+    /// Evaluate with `old` substituted, reducing every intermediate value
+    /// modulo `modulus`. Each operation is carried out in `u128`, so nested
+    /// products cannot overflow for any `modulus`.
+    fn evaluate(&self, old: u64, modulus: u64) -> u64 {
+        match self {
+            Self::Old => old % modulus,
+            Self::Literal(x) => x % modulus,
+            Self::BinOp {
+                left,
+                operator,
+                right,
+            } => {
+                let left = left.evaluate(old, modulus) as u128;
+                let right = right.evaluate(old, modulus) as u128;
+                let result = match operator {
+                    Operator::Add => left + right,
+                    Operator::Mul => left * right,
+                };
+                (result % modulus as u128) as u64
+            }
+        }
+    }
+
+    /// Evaluate without any modular reduction.
+    fn evaluate_exact(&self, old: &BigUint) -> BigUint {
+        match self {
+            Self::Old => old.clone(),
+            Self::Literal(x) => BigUint::from(*x),
+            Self::BinOp {
+                left,
+                operator,
+                right,
+            } => {
+                let (left, right) = (left.evaluate_exact(old), right.evaluate_exact(old));
+                match operator {
+                    Operator::Add => left + right,
+                    Operator::Mul => left * right,
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -71,13 +415,21 @@ impl DecisionData {
             self.target_if_false
         }
     }
+
+    fn decide_exact(&self, x: &BigUint) -> usize {
+        if (x % self.divisor).is_zero() {
+            self.target_if_true
+        } else {
+            self.target_if_false
+        }
+    }
 }
 
 #[derive(Debug)]
 struct Monkey {
     items: VecDeque<u64>,
     n_inspected: u64,
-    operation: Operation,
+    operation: Expr,
     decision_data: DecisionData,
 }
 
@@ -89,14 +441,15 @@ impl Monkey {
     ) -> (u64, usize) {
         self.n_inspected += 1;
 
-        let mut item = self.items.pop_front().unwrap();
+        let item = self.items.pop_front().unwrap();
+        self.inspect(item, worry_level_decrease, divisor_prod)
+    }
 
+    /// Compute the new worry level of `item` and the monkey it is thrown to,
+    /// without touching this monkey's queue.
+    fn inspect(&self, item: u64, worry_level_decrease: bool, divisor_prod: u64) -> (u64, usize) {
         // Worry increases
-        item = match self.operation {
-            Operation::Add(by) => item + by,
-            Operation::Mult(by) => item * by,
-            Operation::Square => item.pow(2),
-        } % divisor_prod;
+        let mut item = self.operation.evaluate(item, divisor_prod);
 
         // Worry decreases
         if worry_level_decrease {
@@ -109,6 +462,19 @@ impl Monkey {
         (item, target)
     }
 
+    /// Reference version of [`Monkey::inspect`] on unbounded worry levels.
+    fn inspect_exact(&self, item: &BigUint, worry_level_decrease: bool) -> (BigUint, usize) {
+        let mut item = self.operation.evaluate_exact(item);
+
+        if worry_level_decrease {
+            item /= 3u32;
+        }
+
+        let target = self.decision_data.decide_exact(&item);
+
+        (item, target)
+    }
+
     fn receive_item(&mut self, item: u64) {
         self.items.push_back(item);
     }
@@ -122,20 +488,51 @@ fn starting_items(s: &str) -> IResult<&str, VecDeque<u64>> {
     )(s)
 }
 
-fn operation(s: &str) -> IResult<&str, Operation> {
-    delimited(
-        tag("Operation: new = old "),
-        map(
-            separated_pair(one_of("+*"), complete::char(' '), alt((digit1, tag("old")))),
-            |(operator, operand)| match (operator, operand) {
-                ('+', operand) => Operation::Add(operand.parse().unwrap()),
-                ('*', "old") => Operation::Square,
-                ('*', operand) => Operation::Mult(operand.parse().unwrap()),
-                _ => panic!("Parsed invalid operation"),
-            },
+fn binary_expr<'a>(
+    operand: fn(&'a str) -> IResult<&'a str, Expr>,
+    symbol: char,
+    operator: Operator,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Expr> {
+    map(
+        pair(
+            operand,
+            many0(preceded(
+                delimited(space0, complete::char(symbol), space0),
+                operand,
+            )),
         ),
-        multispace1,
-    )(s)
+        move |(first, rest)| {
+            rest.into_iter().fold(first, |left, right| Expr::BinOp {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            })
+        },
+    )
+}
+
+fn factor(s: &str) -> IResult<&str, Expr> {
+    alt((
+        value(Expr::Old, tag("old")),
+        map(complete::u64, Expr::Literal),
+        delimited(
+            pair(complete::char('('), space0),
+            expr,
+            pair(space0, complete::char(')')),
+        ),
+    ))(s)
+}
+
+fn term(s: &str) -> IResult<&str, Expr> {
+    binary_expr(factor, '*', Operator::Mul)(s)
+}
+
+fn expr(s: &str) -> IResult<&str, Expr> {
+    binary_expr(term, '+', Operator::Add)(s)
+}
+
+fn operation(s: &str) -> IResult<&str, Expr> {
+    delimited(tag("Operation: new = "), expr, multispace1)(s)
 }
 
 fn decision_fn(s: &str) -> IResult<&str, DecisionData> {
@@ -183,6 +580,7 @@ fn parse(s: &str) -> IResult<&str, Vec<Monkey>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
     const DATA: &str = include_str!("day11/sample.txt");
 
     #[test]
@@ -194,4 +592,149 @@ mod tests {
     fn test_part2() {
         assert_eq!(part2(DATA), 2713310158);
     }
+
+    #[test]
+    fn test_trace() {
+        let (_, mut monkeys) = parse(DATA).unwrap();
+        let (monkey_business, trace) = process_traced(&mut monkeys, 20, true);
+        assert_eq!(monkey_business, 10605);
+
+        assert_eq!(
+            trace.round(1).unwrap().to_string(),
+            "After round 1, the monkeys are holding items with these worry levels:
+Monkey 0: 20, 23, 27, 26
+Monkey 1: 2080, 25, 167, 207, 401, 1046
+Monkey 2: \nMonkey 3: "
+        );
+        assert_eq!(
+            trace.round(20).unwrap().items,
+            vec![
+                vec![10, 12, 14, 26, 34],
+                vec![245, 93, 53, 199, 115],
+                vec![],
+                vec![]
+            ]
+        );
+        assert!(trace.round(21).is_none());
+
+        assert_eq!(
+            trace.events_in_round(1).next(),
+            Some(&InspectionEvent {
+                round: 1,
+                monkey: 0,
+                old: 79,
+                new: 500,
+                divisible: false,
+                target: 3,
+            })
+        );
+        assert_eq!(
+            trace.events.len() as u64,
+            monkeys.iter().map(|monkey| monkey.n_inspected).sum::<u64>()
+        );
+    }
+
+    #[rstest]
+    #[case(20, true)]
+    #[case(1_000, false)]
+    #[case(10_000, false)]
+    fn process_by_item_matches_process(#[case] n_rounds: u64, #[case] worry_level_decrease: bool) {
+        let (_, mut monkeys) = parse(DATA).unwrap();
+        let by_item = process_by_item(&monkeys, n_rounds, worry_level_decrease);
+        assert_eq!(
+            by_item,
+            process(&mut monkeys, n_rounds, worry_level_decrease) as u128
+        );
+    }
+
+    #[rstest]
+    #[case(20, true)]
+    #[case(10, false)]
+    fn modular_shortcut_matches_exact_throws(
+        #[case] n_rounds: u64,
+        #[case] worry_level_decrease: bool,
+    ) {
+        let (_, mut monkeys) = parse(DATA).unwrap();
+        let exact = exact_throws(&monkeys, n_rounds, worry_level_decrease);
+        let (_, trace) = process_traced(&mut monkeys, n_rounds, worry_level_decrease);
+        assert_eq!(
+            trace
+                .events
+                .iter()
+                .map(|event| (event.monkey, event.target))
+                .collect::<Vec<_>>(),
+            exact
+        );
+    }
+
+    #[rstest]
+    // Every sample item has entered its cycle after 175 rounds, and the
+    // cycles last 171 or 448 rounds
+    #[case(623)]
+    #[case(624)]
+    #[case(4_481)]
+    #[case(20_011)]
+    fn process_by_item_matches_process_past_cycles(#[case] n_rounds: u64) {
+        let (_, mut monkeys) = parse(DATA).unwrap();
+        let by_item = process_by_item(&monkeys, n_rounds, false);
+        assert_eq!(by_item, process(&mut monkeys, n_rounds, false) as u128);
+    }
+
+    #[test]
+    fn test_process_by_item_billions_of_rounds() {
+        // The sample repeats every lcm(171, 448) rounds once all items are in
+        // their cycles, so the counts after a billion rounds follow from two
+        // sequential runs that share its remainder
+        const PERIOD: u64 = 76_608;
+        const N_ROUNDS: u64 = 1_000_000_000;
+        let (_, mut monkeys) = parse(DATA).unwrap();
+        let expected = process_by_item(&monkeys, N_ROUNDS, false);
+
+        let mut counts = vec![];
+        let mut n_rounds = N_ROUNDS % PERIOD;
+        for _ in 0..3 {
+            process(&mut monkeys, n_rounds, false);
+            counts.push(
+                monkeys
+                    .iter()
+                    .map(|m| m.n_inspected as u128)
+                    .collect::<Vec<_>>(),
+            );
+            n_rounds = PERIOD;
+        }
+        let per_period = (0..monkeys.len())
+            .map(|i| counts[1][i] - counts[0][i])
+            .collect::<Vec<_>>();
+        assert!((0..monkeys.len()).all(|i| counts[2][i] - counts[1][i] == per_period[i]));
+
+        let n_periods = (N_ROUNDS / PERIOD) as u128;
+        let mut extrapolated = (0..monkeys.len())
+            .map(|i| counts[0][i] + n_periods * per_period[i])
+            .collect::<Vec<_>>();
+        extrapolated.sort_by(|a, b| b.cmp(a));
+        assert_eq!(expected, extrapolated[0] * extrapolated[1]);
+        assert_eq!(expected, 27_142_382_184_098_982_504);
+    }
+
+    #[rstest]
+    #[case("old + 6", 7, 13)]
+    #[case("old * old", 7, 49)]
+    #[case("old * 3 + old", 7, 28)]
+    #[case("old + 3 * old", 7, 28)]
+    #[case("(old + 2) * old", 7, 63)]
+    #[case("( old + 2 )*(old*2)", 7, 126)]
+    fn expr_works(#[case] input: &str, #[case] old: u64, #[case] expected: u64) {
+        let (rest, expr) = expr(input).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(expr.evaluate(old, u64::MAX), expected);
+    }
+
+    #[test]
+    fn test_expr_large_modulus() {
+        let (_, square) = expr("old * old").unwrap();
+        // 2^64 is 1 modulo 2^64 - 1
+        assert_eq!(square.evaluate(1 << 40, u64::MAX), 1 << 16);
+        let (_, double) = expr("old + old").unwrap();
+        assert_eq!(double.evaluate(u64::MAX - 1, u64::MAX), u64::MAX - 2);
+    }
 }
@@ -11,9 +11,20 @@ use nom::{
     IResult,
 };
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Build a [`Packet::List`] from JSON-like syntax, e.g. `packet![[1, [2]], 3]`.
+macro_rules! packet {
+    (@elem [$($inner:tt),* $(,)?]) => { packet![$($inner),*] };
+    (@elem $x:literal) => { Packet::Scalar($x) };
+    ($($elem:tt),* $(,)?) => { Packet::List(vec![$(packet!(@elem $elem)),*]) };
+}
 
 fn main() {
     const PUZZLE_DATA: &str = include_str!("day13/puzzle.txt");
+    if std::env::args().any(|arg| arg == "--explain") {
+        explain_part1(PUZZLE_DATA);
+    }
     println!("Part1: {}", part1(PUZZLE_DATA));
     println!("Part2: {}", part2(PUZZLE_DATA));
 }
@@ -27,10 +38,19 @@ fn part1(data: &str) -> usize {
         .filter_map(|(i, (x, y))| if x < y { Some(i + 1) } else { None })
         .sum()
 }
+
+fn explain_part1(data: &str) {
+    let (_, packet_pairs) = packet_pairs(data).unwrap();
+
+    packet_pairs.iter().enumerate().for_each(|(i, (x, y))| {
+        println!("== Pair {} ==\n{x}\n{y}\n{}\n", i + 1, x.explain_cmp(y));
+    });
+}
+
 fn part2(data: &str) -> usize {
     let (_, packet_pairs) = packet_pairs(data).unwrap();
-    let packet_2 = Packet::List(vec![Packet::List(vec![Packet::Scalar(2)])]);
-    let packet_6 = Packet::List(vec![Packet::List(vec![Packet::Scalar(6)])]);
+    let packet_2 = packet![[2]];
+    let packet_6 = packet![[6]];
 
     let mut packets = packet_pairs
         .iter()
@@ -53,8 +73,8 @@ fn part2(data: &str) -> usize {
         .product()
 }
 
-// TODO: Implement Display
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
 enum Packet {
     Scalar(u8),
     List(Vec<Packet>),
@@ -62,16 +82,138 @@ enum Packet {
 
 impl std::fmt::Display for Packet {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::Scalar(x) => x.to_string(),
-                Self::List(x) => {
-                    format!("[{}]", x.iter().map(|elem| elem.to_owned()).join(","))
+        match self {
+            Self::Scalar(x) => write!(f, "{x}"),
+            Self::List(x) => write!(f, "[{}]", x.iter().format(",")),
+        }
+    }
+}
+
+impl std::str::FromStr for Packet {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+}
+
+impl std::fmt::Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Left => write!(f, "left"),
+            Self::Right => write!(f, "right"),
+        }
+    }
+}
+
+/// What settled a packet comparison.
+#[derive(Debug, PartialEq, Eq)]
+enum Decision {
+    /// Two differing scalars (left, right).
+    Scalars(u8, u8),
+    /// This side ran out of items first.
+    Exhausted(Side),
+    /// Nothing differed.
+    Equal,
+}
+
+/// Trace of [`Packet::explain_cmp`].
+#[derive(Debug, PartialEq, Eq)]
+struct Explanation {
+    /// Indices into the nested lists that lead to the deciding element.
+    path: Vec<usize>,
+    /// Scalars promoted to a single-item list on the way, as the
+    /// (prefix of `path`, side) at which the promotion happened.
+    promotions: Vec<(Vec<usize>, Side)>,
+    decision: Decision,
+}
+
+impl Explanation {
+    fn ordering(&self) -> Ordering {
+        match self.decision {
+            Decision::Scalars(x, y) => x.cmp(&y),
+            Decision::Exhausted(Side::Left) => Ordering::Less,
+            Decision::Exhausted(Side::Right) => Ordering::Greater,
+            Decision::Equal => Ordering::Equal,
+        }
+    }
+}
+
+impl std::fmt::Display for Explanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.ordering() {
+            Ordering::Less => write!(f, "right order")?,
+            Ordering::Greater => write!(f, "wrong order")?,
+            Ordering::Equal => write!(f, "equal")?,
+        }
+        match self.decision {
+            Decision::Scalars(x, y) => write!(f, ": {x} vs {y} at {:?}", self.path)?,
+            Decision::Exhausted(side) => write!(f, ": {side} ran out first at {:?}", self.path)?,
+            Decision::Equal => {}
+        }
+        self.promotions
+            .iter()
+            .try_for_each(|(at, side)| write!(f, "\n  promoted {side} scalar to list at {at:?}"))
+    }
+}
+
+impl Packet {
+    /// Compare like [`Ord::cmp`], but record how the result was decided.
+    fn explain_cmp(&self, other: &Self) -> Explanation {
+        let mut path = vec![];
+        let mut promotions = vec![];
+        let decision = Self::decide(self, other, &mut path, &mut promotions);
+
+        Explanation {
+            path,
+            promotions,
+            decision,
+        }
+    }
+
+    fn decide(
+        left: &Self,
+        right: &Self,
+        path: &mut Vec<usize>,
+        promotions: &mut Vec<(Vec<usize>, Side)>,
+    ) -> Decision {
+        match (left, right) {
+            (Self::Scalar(x), Self::Scalar(y)) if x == y => Decision::Equal,
+            (Self::Scalar(x), Self::Scalar(y)) => Decision::Scalars(*x, *y),
+            (Self::Scalar(x), Self::List(_)) => {
+                promotions.push((path.clone(), Side::Left));
+                Self::decide(&Self::List(vec![Self::Scalar(*x)]), right, path, promotions)
+            }
+            (Self::List(_), Self::Scalar(y)) => {
+                promotions.push((path.clone(), Side::Right));
+                Self::decide(left, &Self::List(vec![Self::Scalar(*y)]), path, promotions)
+            }
+            (Self::List(x), Self::List(y)) => {
+                for (i, (l, r)) in x.iter().zip(y).enumerate() {
+                    let n_promotions = promotions.len();
+                    path.push(i);
+                    match Self::decide(l, r, path, promotions) {
+                        Decision::Equal => {
+                            path.pop();
+                            promotions.truncate(n_promotions);
+                        }
+                        decision => return decision,
+                    }
+                }
+
+                match x.len().cmp(&y.len()) {
+                    Ordering::Less => Decision::Exhausted(Side::Left),
+                    Ordering::Greater => Decision::Exhausted(Side::Right),
+                    Ordering::Equal => Decision::Equal,
                 }
             }
-        )
+        }
     }
 }
 
@@ -110,6 +252,7 @@ fn packet_pairs(input: &str) -> IResult<&str, Vec<(Packet, Packet)>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
     const DATA: &str = include_str!("day13/sample.txt");
 
     #[test]
@@ -121,4 +264,69 @@ mod tests {
     fn test_part2() {
         assert_eq!(part2(DATA), 140);
     }
+
+    #[test]
+    fn test_packet_macro() {
+        assert_eq!(
+            packet![[1, [2]], 3],
+            Packet::List(vec![
+                Packet::List(vec![
+                    Packet::Scalar(1),
+                    Packet::List(vec![Packet::Scalar(2)])
+                ]),
+                Packet::Scalar(3),
+            ])
+        );
+        assert_eq!(packet![], Packet::List(vec![]));
+        assert_eq!(
+            serde_json::to_string(&packet![[1, [2]], 3]).unwrap(),
+            "[[1,[2]],3]"
+        );
+    }
+
+    #[test]
+    fn test_explain_cmp() {
+        let (_, packet_pairs) = packet_pairs(DATA).unwrap();
+        packet_pairs
+            .iter()
+            .for_each(|(x, y)| assert_eq!(x.explain_cmp(y).ordering(), x.cmp(y)));
+
+        assert_eq!(
+            packet![[1], [2, 3, 4]].explain_cmp(&packet![[1], 4]),
+            Explanation {
+                path: vec![1, 0],
+                promotions: vec![(vec![1], Side::Right)],
+                decision: Decision::Scalars(2, 4),
+            }
+        );
+        assert_eq!(
+            packet![[[]]].explain_cmp(&packet![[]]),
+            Explanation {
+                path: vec![0],
+                promotions: vec![],
+                decision: Decision::Exhausted(Side::Right),
+            }
+        );
+    }
+
+    fn arb_packet() -> impl Strategy<Value = Packet> {
+        let leaf = any::<u8>().prop_map(Packet::Scalar);
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop::collection::vec(inner, 0..8).prop_map(Packet::List)
+        })
+        .prop_map(|p| match p {
+            Packet::Scalar(_) => Packet::List(vec![p]),
+            list => list,
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn packet_display_roundtrips(p in arb_packet()) {
+            let displayed = p.to_string();
+            prop_assert_eq!(&displayed, &serde_json::to_string(&p).unwrap());
+            prop_assert_eq!(&displayed.parse::<Packet>().unwrap(), &p);
+            prop_assert_eq!(packet(&displayed).unwrap(), ("", p));
+        }
+    }
 }
@@ -1,6 +1,4 @@
 #![feature(iter_collect_into, array_windows)]
-use std::collections::BTreeMap;
-
 use itertools::Itertools;
 use nom::{
     bytes::complete::tag, character::complete, combinator::map, multi::separated_list1,
@@ -11,6 +9,7 @@ fn main() {
     const PUZZLE_DATA: &str = include_str!("day14/puzzle.txt");
     println!("Part1: {}", part1(PUZZLE_DATA));
     println!("Part2: {}", part2(PUZZLE_DATA));
+    println!("Part2 (reachable): {}", part2_reachable(PUZZLE_DATA));
 }
 
 fn part1(data: &str) -> usize {
@@ -25,6 +24,11 @@ fn part2(data: &str) -> usize {
     scan.sand_count
 }
 
+fn part2_reachable(data: &str) -> usize {
+    let (_, scan) = cave_scan_with_floor(data).unwrap();
+    scan.count_reachable_sand()
+}
+
 type Position = (usize, usize);
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -46,71 +50,143 @@ impl Item {
 
 const SAND_SOURCE_POSITION: Position = (500, 0);
 
+/// Dense, row-major scan of the cave, offset so that column `0` maps to `x_min`.
 struct CaveScan {
-    data: BTreeMap<Position, Item>,
+    grid: Vec<Option<Item>>,
+    x_min: usize,
+    width: usize,
+    lowest_rock_depth: usize,
     sand_count: usize,
 }
 
 impl CaveScan {
-    fn new(data: BTreeMap<Position, Item>) -> Self {
-        Self {
-            data,
+    fn new(rocks: Vec<Position>) -> Self {
+        let x_min = rocks
+            .iter()
+            .map(|(x, _)| *x)
+            .chain([SAND_SOURCE_POSITION.0])
+            .min()
+            .unwrap();
+        let x_max = rocks
+            .iter()
+            .map(|(x, _)| *x)
+            .chain([SAND_SOURCE_POSITION.0])
+            .max()
+            .unwrap();
+        let lowest_rock_depth = rocks.iter().map(|(_, y)| *y).max().unwrap();
+
+        // Pad by one column on each side so that sand may always step diagonally
+        // before it falls into the abyss.
+        let x_min = x_min - 1;
+        let width = x_max - x_min + 2;
+        let height = lowest_rock_depth + 1;
+
+        let mut scan = Self {
+            grid: (0..width * height).map(|_| None).collect(),
+            x_min,
+            width,
+            lowest_rock_depth,
             sand_count: 0,
-        }
+        };
+        rocks
+            .into_iter()
+            .for_each(|pos| scan.insert(pos, Item::Rock));
+        scan
+    }
+
+    fn index(&self, (x, y): Position) -> usize {
+        y * self.width + (x - self.x_min)
+    }
+
+    fn get(&self, pos: Position) -> Option<&Item> {
+        self.grid[self.index(pos)].as_ref()
     }
 
+    fn insert(&mut self, pos: Position, item: Item) {
+        let idx = self.index(pos);
+        self.grid[idx] = Some(item);
+    }
+
+    /// Drop sand until it either flows into the abyss or blocks the source.
+    ///
+    /// The path of the falling grain is kept on a stack. Once a grain settles,
+    /// the next one would retrace the same path up to the settled grain's parent,
+    /// so the simulation resumes from there instead of from the source.
     fn drip_sand(&mut self) {
-        let mut current_sand = SAND_SOURCE_POSITION;
-        let lowest_rock_depth = *self.data.iter().map(|((_, y), _)| y).max().unwrap();
-        loop {
-            // println!("{self}\n");
-            if current_sand.1 >= lowest_rock_depth || self.data.contains_key(&SAND_SOURCE_POSITION)
-            {
+        let mut path = vec![SAND_SOURCE_POSITION];
+
+        while let Some(&current_sand) = path.last() {
+            if current_sand.1 >= self.lowest_rock_depth {
                 return;
             }
 
-            let lower_left = (current_sand.0 - 1, current_sand.1 + 1);
             let down = (current_sand.0, current_sand.1 + 1);
+            let lower_left = (current_sand.0 - 1, current_sand.1 + 1);
             let lower_right = (current_sand.0 + 1, current_sand.1 + 1);
 
-            match (
-                self.data.get(&lower_left),
-                self.data.get(&down),
-                self.data.get(&lower_right),
-            ) {
-                (Some(_), Some(_), Some(_)) => {
+            match [down, lower_left, lower_right]
+                .into_iter()
+                .find(|&pos| self.get(pos).is_none())
+            {
+                Some(next) => path.push(next),
+                None => {
                     self.sand_count += 1;
-                    self.data.insert(current_sand, Item::Sand);
-                    current_sand = SAND_SOURCE_POSITION;
-                }
-                (_, None, _) => {
-                    current_sand = down;
-                }
-                (None, _, _) => {
-                    current_sand = lower_left;
-                }
-                (_, _, None) => {
-                    current_sand = lower_right;
+                    self.insert(current_sand, Item::Sand);
+                    path.pop();
                 }
             }
         }
     }
 }
 
-impl std::fmt::Display for CaveScan {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let x_min = self.data.iter().map(|((x, _), _)| x).min().unwrap();
-        let x_max = self.data.iter().map(|((x, _), _)| x).max().unwrap();
-        let y_max = self.data.iter().map(|((_, y), _)| y).max().unwrap();
+impl CaveScan {
+    /// Count the cells that sand can reach from the source without simulating
+    /// any grains.
+    ///
+    /// With a floor beneath the cave every reachable cell ends up filled, and a
+    /// cell is reachable iff it is free and one of the three cells above it
+    /// (up-left, up, up-right) is reachable. Each row is derived from the one
+    /// above it. Without a floor sand escapes into the abyss, so this only
+    /// agrees with [`CaveScan::drip_sand`] on scans built by
+    /// [`cave_scan_with_floor`].
+    fn count_reachable_sand(&self) -> usize {
+        let mut reachable = vec![false; self.width];
+        reachable[SAND_SOURCE_POSITION.0 - self.x_min] = self.get(SAND_SOURCE_POSITION).is_none();
+        let mut count = reachable.iter().filter(|&&r| r).count();
+
+        for y in (SAND_SOURCE_POSITION.1 + 1)..self.lowest_rock_depth {
+            reachable = (0..self.width)
+                .map(|col| {
+                    self.grid[y * self.width + col].is_none()
+                        && reachable[col.saturating_sub(1)..(col + 2).min(self.width)]
+                            .iter()
+                            .any(|&r| r)
+                })
+                .collect();
+            count += reachable.iter().filter(|&&r| r).count();
+        }
 
-        let mut arr = vec![vec!['.'; x_max - x_min + 1]; *y_max + 1];
+        count
+    }
+}
 
-        self.data
-            .iter()
-            .for_each(|(&(x, y), item)| arr[y][x - x_min] = item.to_symbol());
+impl std::fmt::Display for CaveScan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut arr = self
+            .grid
+            .chunks(self.width)
+            .map(|row| {
+                row.iter()
+                    .map(|item| item.as_ref().map_or('.', Item::to_symbol))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
 
         // Add sand source
-        arr[SAND_SOURCE_POSITION.1][SAND_SOURCE_POSITION.0 - x_min] = Item::SandSource.to_symbol();
+        if self.get(SAND_SOURCE_POSITION).is_none() {
+            arr[SAND_SOURCE_POSITION.1][SAND_SOURCE_POSITION.0 - self.x_min] =
+                Item::SandSource.to_symbol();
+        }
 
         write!(
             f,
@@ -160,37 +236,32 @@ fn rock_line(input: &str) -> IResult<&str, Vec<Position>> {
     })(input)
 }
 
-fn cave_scan(input: &str) -> IResult<&str, CaveScan> {
+fn rock_positions(input: &str) -> IResult<&str, Vec<Position>> {
     map(
         separated_list1(complete::newline, rock_line),
-        |rock_lines| {
-            CaveScan::new(
-                rock_lines
-                    .into_iter()
-                    .flatten()
-                    .map(|pos| (pos, Item::Rock))
-                    .collect(),
-            )
-        },
+        |rock_lines| rock_lines.into_iter().flatten().collect(),
     )(input)
 }
 
+fn cave_scan(input: &str) -> IResult<&str, CaveScan> {
+    map(rock_positions, CaveScan::new)(input)
+}
+
 fn cave_scan_with_floor(input: &str) -> IResult<&str, CaveScan> {
-    let (output, mut cave_scan) = cave_scan(input)?;
+    let (output, mut rocks) = rock_positions(input)?;
 
-    let x_min = *cave_scan.data.iter().map(|((x, _), _)| x).min().unwrap();
-    let x_max = *cave_scan.data.iter().map(|((x, _), _)| x).max().unwrap();
-    let y_max = *cave_scan.data.iter().map(|((_, y), _)| y).max().unwrap();
+    let x_min = *rocks.iter().map(|(x, _)| x).min().unwrap();
+    let x_max = *rocks.iter().map(|(x, _)| x).max().unwrap();
+    let floor_depth = *rocks.iter().map(|(_, y)| y).max().unwrap() + 2;
 
-    const PADDING: usize = 400;
+    // Sand spreads at most one column per row, so the floor only needs to span
+    // the triangle below the source.
+    let floor_x_min = x_min.min(SAND_SOURCE_POSITION.0 - floor_depth);
+    let floor_x_max = x_max.max(SAND_SOURCE_POSITION.0 + floor_depth);
 
-    cave_scan.data.extend(
-        ((x_min - PADDING)..=(x_max + PADDING))
-            .cartesian_product([y_max + 2])
-            .map(|pos| (pos, Item::Rock)),
-    );
+    rocks.extend((floor_x_min..=floor_x_max).cartesian_product([floor_depth]));
 
-    Ok((output, cave_scan))
+    Ok((output, CaveScan::new(rocks)))
 }
 
 #[cfg(test)]
@@ -207,4 +278,11 @@ mod tests {
     fn test_part2() {
         assert_eq!(part2(DATA), 93);
     }
+
+    #[test]
+    fn test_part2_reachable() {
+        const PUZZLE_DATA: &str = include_str!("day14/puzzle.txt");
+        assert_eq!(part2_reachable(DATA), 93);
+        assert_eq!(part2_reachable(PUZZLE_DATA), part2(PUZZLE_DATA));
+    }
 }
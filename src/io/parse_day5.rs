@@ -1,29 +1,59 @@
-use crate::utils::transpose;
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range};
 
 use nom::{
-    branch::alt,
-    bytes::complete::tag,
-    character::complete::{self, char, newline, one_of, space0, space1},
-    multi::{many1, separated_list1},
-    sequence::{delimited, separated_pair, terminated},
+    bytes::complete::{is_not, tag},
+    character::complete::{self, char, space0},
+    sequence::{delimited, separated_pair},
     IResult,
 };
 
-fn crate_id(s: &str) -> IResult<&str, char> {
-    delimited(char('['), one_of("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), char(']'))(s)
+/// Stacks of crates by their ID, from bottom to top
+pub type Stacks = HashMap<u32, Vec<String>>;
+
+/// Problem with the input, along with the (1-based) line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+fn crate_id(s: &str) -> IResult<&str, &str> {
+    delimited(char('['), is_not("[] \t"), char(']'))(s)
 }
 
-fn empty_crate(s: &str) -> IResult<&str, char> {
-    delimited(char(' '), char(' '), char(' '))(s)
+/// Every item on a line, with the columns it spans
+fn positioned<'a, O>(
+    line: &'a str,
+    mut item: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> Result<Vec<(Range<usize>, O)>, String> {
+    let mut items = vec![];
+    let mut rest = line;
+
+    loop {
+        (rest, _) = space0::<_, ()>(rest).unwrap();
+        if rest.is_empty() {
+            return Ok(items);
+        }
+
+        let start = line.len() - rest.len();
+        let output;
+        (rest, output) = item(rest).map_err(|_| format!("Unexpected {rest:?}"))?;
+        items.push((start..line.len() - rest.len(), output));
+    }
 }
 
-fn crate_line(s: &str) -> IResult<&str, Vec<char>> {
-    separated_list1(char(' '), alt((crate_id, empty_crate)))(s)
+fn crate_line(s: &str) -> Result<Vec<(Range<usize>, &str)>, String> {
+    positioned(s, crate_id)
 }
 
-fn stack_ids(s: &str) -> IResult<&str, Vec<u32>> {
-    delimited(space1, separated_list1(space1, complete::u32), space0)(s)
+fn stack_ids(s: &str) -> Result<Vec<(Range<usize>, u32)>, String> {
+    positioned(s, complete::u32)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -33,6 +63,16 @@ pub struct Command {
     pub dst_stack: u32,
 }
 
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "move {} from {} to {}",
+            self.num_crates_to_move, self.src_stack, self.dst_stack
+        )
+    }
+}
+
 fn command(s: &str) -> IResult<&str, Command> {
     let (s, num_crates_to_move) = delimited(tag("move "), complete::u32, tag(" from "))(s)?;
     let (s, (src_stack, dst_stack)) = separated_pair(complete::u32, tag(" to "), complete::u32)(s)?;
@@ -46,27 +86,111 @@ fn command(s: &str) -> IResult<&str, Command> {
     ))
 }
 
-pub fn parse(s: &str) -> (HashMap<u32, Vec<char>>, Vec<Command>) {
-    let (s, crates_untransposed) =
-        terminated(separated_list1(newline, crate_line), newline)(s).unwrap();
-    let (s, stack_ids) = terminated(stack_ids, many1(newline))(s).unwrap();
-    let (_, commands) = separated_list1(newline, command)(s).unwrap();
-
-    let crates_array = transpose(crates_untransposed)
-        .into_iter()
-        .map(|crates| {
-            let mut crates = crates
-                .into_iter()
-                .filter(|&x| x != ' ')
-                .collect::<Vec<char>>();
-            crates.reverse();
-            crates
-        })
-        .collect::<Vec<Vec<char>>>();
-
-    let hm = HashMap::from_iter(stack_ids.into_iter().zip(crates_array));
-
-    (hm, commands)
+/// Parse the drawing of the stacks followed by the rearrangement procedure.
+///
+/// Each crate belongs to the stack whose ID is written below it, so labels
+/// can be longer than one character and IDs can have several digits as long
+/// as they line up. Commands are checked against the stack heights at the
+/// point they run.
+pub fn parse(s: &str) -> Result<(Stacks, Vec<Command>), ParseError> {
+    let lines = s.lines().map(str::trim_end).collect::<Vec<_>>();
+    let error = |line: usize, message: String| ParseError {
+        line: line + 1,
+        message,
+    };
+
+    let drawing_len = lines
+        .iter()
+        .position(|line| line.is_empty())
+        .unwrap_or(lines.len());
+    if drawing_len == 0 {
+        return Err(error(0, "Missing drawing of the stacks".to_owned()));
+    }
+
+    let id_line = drawing_len - 1;
+    let ids = stack_ids(lines[id_line]).map_err(|message| error(id_line, message))?;
+    let mut stacks = Stacks::new();
+    for (_, id) in &ids {
+        if stacks.insert(*id, vec![]).is_some() {
+            return Err(error(id_line, format!("Stack {id} appears twice")));
+        }
+    }
+
+    // Fill the stacks from the bottom up
+    for (level, line_idx) in (0..id_line).rev().enumerate() {
+        let crates = crate_line(lines[line_idx]).map_err(|message| error(line_idx, message))?;
+
+        for (span, label) in crates {
+            let mut below = ids
+                .iter()
+                .filter(|(id_span, _)| id_span.start < span.end && span.start < id_span.end);
+            let id = match (below.next(), below.next()) {
+                (Some((_, id)), None) => id,
+                (None, _) => {
+                    return Err(error(
+                        line_idx,
+                        format!("Crate [{label}] is above no stack"),
+                    ))
+                }
+                (Some(_), Some(_)) => {
+                    return Err(error(
+                        line_idx,
+                        format!("Crate [{label}] is above several stacks"),
+                    ))
+                }
+            };
+
+            let stack = stacks.get_mut(id).unwrap();
+            if stack.len() != level {
+                return Err(error(line_idx, format!("Crate [{label}] is floating")));
+            }
+            stack.push(label.to_owned());
+        }
+    }
+
+    let mut heights = stacks
+        .iter()
+        .map(|(&id, crates)| (id, crates.len()))
+        .collect::<HashMap<_, _>>();
+    let mut commands = vec![];
+
+    for (line_idx, line) in lines.iter().enumerate().skip(drawing_len) {
+        if line.is_empty() {
+            continue;
+        }
+
+        let command = match command(line) {
+            Ok(("", command)) => command,
+            _ => return Err(error(line_idx, format!("Invalid command {line:?}"))),
+        };
+        let src_height = *heights
+            .get(&command.src_stack)
+            .ok_or_else(|| error(line_idx, format!("No stack {}", command.src_stack)))?;
+        if !heights.contains_key(&command.dst_stack) {
+            return Err(error(line_idx, format!("No stack {}", command.dst_stack)));
+        }
+        if command.src_stack == command.dst_stack {
+            return Err(error(
+                line_idx,
+                format!("Stack {} moves onto itself", command.src_stack),
+            ));
+        }
+        if command.num_crates_to_move > src_height {
+            return Err(error(
+                line_idx,
+                format!(
+                    "Cannot move {} crates from stack {} holding {src_height}",
+                    command.num_crates_to_move, command.src_stack
+                ),
+            ));
+        }
+
+        *heights.get_mut(&command.src_stack).unwrap() -= command.num_crates_to_move;
+        *heights.get_mut(&command.dst_stack).unwrap() += command.num_crates_to_move;
+        commands.push(command);
+    }
+
+    Ok((stacks, commands))
 }
 
 #[cfg(test)]
@@ -79,32 +203,31 @@ mod tests {
     #[rstest]
     #[case("[A]", "A")]
     #[case("[E]", "E")]
-    fn crate_id_works(#[case] input: &str, #[case] expected: char) {
+    #[case("[Ab1]", "Ab1")]
+    fn crate_id_works(#[case] input: &str, #[case] expected: &str) {
         let (remaining, output) = crate_id(input).unwrap();
         assert_eq!(remaining, "");
         assert_eq!(output, expected);
     }
 
     #[rstest]
-    #[case("[A] [B] [C]", vec!['A', 'B', 'C'])]
-    #[case("    [E]", vec![' ', 'E'])]
-    #[case("        [E]", vec![' ', ' ', 'E'])]
-    #[case("    [E]    ", vec![' ', 'E', ' '])]
-    fn crate_line_works(#[case] input: &str, #[case] expected: Vec<char>) {
-        let (remaining, output) = crate_line(input).unwrap();
-        assert_eq!(remaining, "");
-        assert_eq!(output, expected);
+    #[case("[A] [B] [C]", vec![(0..3, "A"), (4..7, "B"), (8..11, "C")])]
+    #[case("    [E]", vec![(4..7, "E")])]
+    #[case("        [E]", vec![(8..11, "E")])]
+    #[case("    [E]    ", vec![(4..7, "E")])]
+    #[case("[AB]  [C]", vec![(0..4, "AB"), (6..9, "C")])]
+    fn crate_line_works(#[case] input: &str, #[case] expected: Vec<(Range<usize>, &str)>) {
+        assert_eq!(crate_line(input).unwrap(), expected);
     }
 
     #[rstest]
-    #[case(" 1 ", vec![1])]
-    #[case(" 1   2 ", vec![1, 2])]
-    #[case(" 1   2   5 ", vec![1, 2, 5])]
-    #[case(" 1   2   5", vec![1, 2, 5])]
-    fn stack_ids_works(#[case] input: &str, #[case] expected: Vec<u32>) {
-        let (remaining, output) = stack_ids(input).unwrap();
-        assert_eq!(remaining, "");
-        assert_eq!(output, expected);
+    #[case(" 1 ", vec![(1..2, 1)])]
+    #[case(" 1   2 ", vec![(1..2, 1), (5..6, 2)])]
+    #[case(" 1   2   5 ", vec![(1..2, 1), (5..6, 2), (9..10, 5)])]
+    #[case(" 1   2   5", vec![(1..2, 1), (5..6, 2), (9..10, 5)])]
+    #[case(" 9  10", vec![(1..2, 9), (4..6, 10)])]
+    fn stack_ids_works(#[case] input: &str, #[case] expected: Vec<(Range<usize>, u32)>) {
+        assert_eq!(stack_ids(input).unwrap(), expected);
     }
 
     #[rstest]
@@ -115,6 +238,7 @@ mod tests {
         let (remaining, output) = command(input).unwrap();
         assert_eq!(remaining, "");
         assert_eq!(output, expected);
+        assert_eq!(output.to_string(), input);
     }
 
     #[test]
@@ -127,18 +251,12 @@ mod tests {
 move 1 from 2 to 1
 move 3 from 1 to 3";
 
-        let (stacks, commands) = parse(data);
+        let (stacks, commands) = parse(data).unwrap();
 
         let mut expected_stacks = HashMap::new();
-        expected_stacks.insert(1, vec!['Z', 'N']);
-        expected_stacks.insert(2, vec!['M', 'C', 'D']);
-        expected_stacks.insert(3, vec!['P']);
-
-        // let expected_stacks = vec![
-        //     vec!["Z", "N"],
-        //     vec!["M", "C", "D"],
-        //     vec!["P"]
-        // ];
+        expected_stacks.insert(1, vec!["Z".to_owned(), "N".to_owned()]);
+        expected_stacks.insert(2, vec!["M".to_owned(), "C".to_owned(), "D".to_owned()]);
+        expected_stacks.insert(3, vec!["P".to_owned()]);
 
         let expected_commands = vec![
             Command {
@@ -156,4 +274,54 @@ move 3 from 1 to 3";
         assert_eq!(stacks, expected_stacks);
         assert_eq!(commands, expected_commands);
     }
+
+    #[test]
+    fn parse_generalised_drawing() {
+        // Not using a `\` continuation, as that would strip the leading spaces
+        let data = "                                        [Q]
+[AB]                                    [CD]
+ 1   2   3   4   5   6   7   8   9  10   11
+
+move 2 from 11 to 1";
+
+        let (stacks, commands) = parse(data).unwrap();
+        assert_eq!(stacks.len(), 11);
+        assert_eq!(stacks[&1], ["AB"]);
+        assert_eq!(stacks[&11], ["CD", "Q"]);
+        assert!(stacks[&10].is_empty());
+        assert_eq!(commands.len(), 1);
+    }
+
+    #[rstest]
+    #[case("[A]\n 1\n\nmove 2 from 1 to 1", 4, "Stack 1 moves onto itself")]
+    #[case(
+        "[A]\n 1   2\n\nmove 2 from 1 to 2",
+        4,
+        "Cannot move 2 crates from stack 1 holding 1"
+    )]
+    #[case(
+        "[A]\n 1   2\n\nmove 1 from 1 to 2\nmove 1 from 1 to 2",
+        5,
+        "Cannot move 1 crates from stack 1 holding 0"
+    )]
+    #[case("[A]\n 1   2\n\nmove 1 from 3 to 2", 4, "No stack 3")]
+    #[case(
+        "[A]\n 1   2\n\nmove one from 1 to 2",
+        4,
+        "Invalid command \"move one from 1 to 2\""
+    )]
+    #[case("[A] (B)\n 1   2", 1, "Unexpected \"(B)\"")]
+    #[case("        [A]\n 1   2", 1, "Crate [A] is above no stack")]
+    #[case("    [A]\n[B]\n 1   2", 1, "Crate [A] is floating")]
+    #[case(" 1   1", 1, "Stack 1 appears twice")]
+    #[case("\nmove 1 from 1 to 2", 1, "Missing drawing of the stacks")]
+    fn parse_reports_errors(#[case] input: &str, #[case] line: usize, #[case] message: &str) {
+        assert_eq!(
+            parse(input),
+            Err(ParseError {
+                line,
+                message: message.to_owned()
+            })
+        );
+    }
 }